@@ -1,9 +1,59 @@
+use std::str::FromStr;
+
 use structopt::StructOpt;
 
+use crate::ci::MatrixFilter;
+
 #[derive(StructOpt)]
 pub struct Args {
     #[structopt(subcommand)]
     pub subcommand: Option<Subcommand>,
+    /// Only run matrix combinations where `key` has this value, for
+    /// example `--matrix os=ubuntu-latest`. May be given multiple times.
+    /// Combinations run one at a time, unless their `max-parallel`
+    /// allows more.
+    #[structopt(long = "matrix", number_of_values = 1)]
+    pub matrix: Vec<MatrixFilter>,
+    /// The git hook belay is being run from, either `pre-commit` or
+    /// `pre-push`. This decides which changes are checked against
+    /// path filters.
+    #[structopt(long = "from-hook")]
+    pub from_hook: Option<HookType>,
+    /// Simulate this event instead of the push and pull request belay
+    /// expects, for example `--event workflow_dispatch`.
+    #[structopt(long = "event")]
+    pub event: Option<String>,
+    /// Give an input of the simulated event, for example
+    /// `--input version=1.2.3`. May be given multiple times.
+    #[structopt(
+        long = "input",
+        number_of_values = 1,
+        requires = "event",
+        parse(try_from_str = parse_input)
+    )]
+    pub inputs: Vec<(String, String)>,
+    /// Hide the output within `::group::` workflow commands, only
+    /// showing the title of each group.
+    #[structopt(long = "collapse-groups")]
+    pub collapse_groups: bool,
+    /// Run GitHub jobs even if their `runs-on` labels are for a
+    /// different operating system.
+    #[structopt(long = "ignore-runs-on")]
+    pub ignore_runs_on: bool,
+    /// Run GitHub jobs with a `container` or `services` using this
+    /// docker compatible program, for example `--container-runtime
+    /// podman`. Overrides `container_runtime` in the config file.
+    #[structopt(long = "container-runtime")]
+    pub container_runtime: Option<String>,
+}
+
+fn parse_input(input: &str) -> Result<(String, String), String> {
+    let mut parts = input.splitn(2, '=');
+
+    match (parts.next(), parts.next()) {
+        (Some(key), Some(value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected `key=value`, found `{}`", input)),
+    }
 }
 
 #[derive(StructOpt)]
@@ -28,3 +78,18 @@ impl HookType {
         }
     }
 }
+
+impl FromStr for HookType {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "pre-commit" => Ok(HookType::Commit),
+            "pre-push" => Ok(HookType::Push),
+            _ => Err(format!(
+                "expected `pre-commit` or `pre-push`, found `{}`",
+                input
+            )),
+        }
+    }
+}
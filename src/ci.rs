@@ -1,12 +1,592 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+    path::Path,
+    str::FromStr,
+    time::Duration,
+};
+
 use crate::Config;
+use github::{
+    expr::{self, ExpressionError},
+    filter::Filter,
+};
 
 pub mod github;
 pub mod gitlab;
 
+/// TaskKey identifies what a task runs, see `Task::key`.
+pub type TaskKey = (String, Option<String>, Vec<(String, String)>);
+
 pub struct Task {
+    /// The id other tasks can refer to the task's outputs by.
+    pub id: Option<String>,
     pub name: Option<String>,
     pub command: String,
+    /// Environment variables to set for the command, in addition
+    /// to those inherited from belay.
+    pub env: HashMap<String, String>,
+    /// The directory to run the command in, relative to the
+    /// root of the repository.
+    pub working_directory: Option<String>,
+    /// The shell to run the command with, or `None` to use the
+    /// platform default.
+    pub shell: Option<Shell>,
+    /// Whether the shell is the one GitHub Actions uses by default,
+    /// which depends on whether bash is installed where the task runs.
+    pub default_shell: bool,
+    /// Why the task can't be run locally, such as for a step which
+    /// uses an action from the marketplace.
+    pub skip_reason: Option<String>,
+    /// An expression which must be true for the task to run. Tasks
+    /// without a condition only run if no earlier task in the job
+    /// failed.
+    condition: Option<String>,
+    /// Whether a failure of the task is tolerated. This may be an
+    /// expression until expressions are evaluated.
+    continue_on_error: Option<String>,
+    /// How many minutes the task may run for before it is killed.
+    /// This may be an expression until expressions are evaluated.
+    timeout_minutes: Option<String>,
+    /// The inputs of the composite actions the task was inlined from,
+    /// outermost first. Each is evaluated in the `inputs` context of
+    /// the one before it.
+    inputs: Vec<HashMap<String, String>>,
+    /// The uses of composite actions the task was inlined from,
+    /// outermost first. Their `env` includes the workflow and job's.
+    composites: Vec<github::action::Composite>,
+    /// The context for evaluating `${{ }}` expressions, for providers
+    /// which support them.
+    context: Option<expr::Context>,
+    /// For GitLab jobs, the lines which run one after another in the
+    /// same shell. The command is built from those which aren't
+    /// blacklisted.
+    script_lines: Option<Vec<String>>,
+    /// Whether the task runs even once its job has failed, like
+    /// GitLab's `after_script`.
+    always: bool,
+}
+
+impl Task {
+    /// Evaluates any expressions in the task, replacing them with
+    /// their values, given the status of its job so far.
+    pub fn evaluate_expressions(
+        &mut self,
+        workspace: &Path,
+        trigger: Option<&Trigger>,
+        status: JobStatus,
+    ) -> Result<(), ExpressionError> {
+        let mut context = match self.context_for(workspace, trigger, status, self.inputs.len())? {
+            Some(context) => context,
+            None => return Ok(()),
+        };
+
+        // Environment variables can't refer to each other, so they are
+        // evaluated before being added to the `env` context.
+        for value in self.env.values_mut() {
+            *value = expr::interpolate(value, &context)?;
+        }
+        context.values.insert(
+            "env".into(),
+            expr::Value::Object(
+                self.env
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.as_str().into()))
+                    .collect(),
+            ),
+        );
+
+        let interpolate = |s: &String| expr::interpolate(s, &context);
+        self.name = self.name.as_ref().map(interpolate).transpose()?;
+        self.command = interpolate(&self.command)?;
+        self.working_directory = self
+            .working_directory
+            .as_ref()
+            .map(interpolate)
+            .transpose()?;
+        self.continue_on_error = self
+            .continue_on_error
+            .as_ref()
+            .map(interpolate)
+            .transpose()?;
+        self.timeout_minutes = self.timeout_minutes.as_ref().map(interpolate).transpose()?;
+        if let Some(minutes) = &self.timeout_minutes {
+            timeout_from(minutes)?;
+        }
+        self.context = Some(context);
+
+        Ok(())
+    }
+
+    /// Returns the task's expression context given the status of its
+    /// job so far, with the `inputs` context of the first `depth` of
+    /// the composite actions and shims it was inlined from.
+    fn context_for(
+        &self,
+        workspace: &Path,
+        trigger: Option<&Trigger>,
+        status: JobStatus,
+        depth: usize,
+    ) -> Result<Option<expr::Context>, ExpressionError> {
+        let mut context = match &self.context {
+            Some(context) => context.clone(),
+            None => return Ok(None),
+        };
+        context.workspace = workspace.to_path_buf();
+        context.status = status;
+        context.values.insert(
+            "job".into(),
+            expr::object(vec![("status", status.to_string().as_str().into())]),
+        );
+        context
+            .values
+            .insert("github".into(), github::context::github(trigger, workspace));
+
+        for inputs in &self.inputs[..depth] {
+            let values = inputs
+                .iter()
+                .map(|(k, v)| Ok((k.clone(), expr::interpolate(v, &context)?.as_str().into())))
+                .collect::<Result<_, ExpressionError>>()?;
+            context
+                .values
+                .insert("inputs".into(), expr::Value::Object(values));
+        }
+
+        Ok(Some(context))
+    }
+
+    /// Returns the uses of composite actions the task was inlined from,
+    /// outermost first.
+    pub fn composites(&self) -> &[github::action::Composite] {
+        &self.composites
+    }
+
+    /// Returns whether the step which used the composite action should
+    /// run, evaluating its condition in the context of that step, with
+    /// the given `steps` context.
+    pub fn composite_should_run(
+        &self,
+        composite: &github::action::Composite,
+        steps: expr::Value,
+        workspace: &Path,
+        trigger: Option<&Trigger>,
+        status: JobStatus,
+    ) -> Result<bool, ExpressionError> {
+        let mut context =
+            match self.context_for(workspace, trigger, status, composite.caller_inputs)? {
+                Some(context) => context,
+                None => return Ok(status == JobStatus::Success),
+            };
+        context.values.insert("steps".into(), steps);
+        let env = composite
+            .env
+            .iter()
+            .map(|(k, v)| Ok((k.clone(), expr::interpolate(v, &context)?.as_str().into())))
+            .collect::<Result<_, ExpressionError>>()?;
+        context
+            .values
+            .insert("env".into(), expr::Value::Object(env));
+
+        match &composite.condition {
+            Some(condition) => expr::evaluate_condition(condition, &context),
+            None => Ok(status == JobStatus::Success),
+        }
+    }
+
+    /// Returns the context of the steps of the composite action, in
+    /// which its outputs are evaluated, except for the `steps` context.
+    pub fn composite_context(
+        &self,
+        composite: &github::action::Composite,
+        workspace: &Path,
+        trigger: Option<&Trigger>,
+        status: JobStatus,
+    ) -> Result<Option<expr::Context>, ExpressionError> {
+        self.context_for(workspace, trigger, status, composite.caller_inputs + 1)
+    }
+
+    /// Adds a value to the expression context of the task, for
+    /// providers which support expressions.
+    pub fn insert_context(&mut self, name: &str, value: expr::Value) {
+        if let Some(context) = &mut self.context {
+            context.values.insert(name.to_string(), value);
+        }
+    }
+
+    /// Returns whether a failure of the task should be reported
+    /// without failing its job.
+    pub fn continues_on_error(&self) -> bool {
+        is_true(&self.continue_on_error)
+    }
+
+    /// Returns how long the task may run for before it is killed.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout_minutes
+            .as_deref()
+            .and_then(|minutes| timeout_from(minutes).ok().flatten())
+    }
+
+    /// Returns whether the task should run, given the status of its
+    /// job so far. Expressions must be evaluated first.
+    pub fn should_run(&self, status: JobStatus) -> Result<bool, ExpressionError> {
+        if self.always {
+            return Ok(true);
+        }
+
+        match (&self.condition, &self.context) {
+            (Some(condition), Some(context)) => expr::evaluate_condition(condition, context),
+            _ => Ok(status == JobStatus::Success),
+        }
+    }
+
+    /// Returns a key identifying what this task runs. The same command
+    /// run in a different directory or environment is a different check.
+    pub fn key(&self) -> TaskKey {
+        let mut env = self
+            .env
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect::<Vec<_>>();
+        env.sort();
+
+        (self.command.clone(), self.working_directory.clone(), env)
+    }
+}
+
+/// Job is a group of tasks which run one after another. Once a task
+/// fails, the rest of the tasks in the job are skipped unless their
+/// condition says otherwise.
+pub struct Job {
+    /// The id of the job within its CI configuration. Matrix jobs
+    /// share the id of the job they were expanded from.
+    pub id: String,
+    pub name: String,
+    pub tasks: Vec<Task>,
+    /// The ids of the jobs which must complete before this one.
+    pub needs: Vec<String>,
+    /// An expression which must be true for the job to run.
+    condition: Option<String>,
+    /// Whether a failure of the job is tolerated. This may be an
+    /// expression until the job's condition is evaluated.
+    continue_on_error: Option<String>,
+    /// How many minutes the job may run for before it is cancelled.
+    /// This may be an expression until the job's condition is evaluated.
+    timeout_minutes: Option<String>,
+    /// The context for evaluating the condition, for providers which
+    /// support expressions.
+    context: Option<expr::Context>,
     applicability: Vec<Applicability>,
+    /// The matrix values this job was created for, if it uses a
+    /// matrix.
+    matrix: Vec<(String, String)>,
+    /// Whether a failure of this combination cancels the job's
+    /// remaining combinations. This may be an expression until the
+    /// job's condition is evaluated.
+    fail_fast: Option<String>,
+    /// The most combinations of the job's matrix which may run at once.
+    max_parallel: Option<String>,
+    /// For jobs expanded from reusable workflows, how each workflow
+    /// was called, outermost first.
+    calls: Vec<github::call::Binding>,
+    /// The labels of the runner the job runs on. These may be
+    /// expressions until the job's condition is evaluated.
+    runs_on: Vec<String>,
+    /// The container the job's steps run in, and the service
+    /// containers started alongside it. These may contain expressions
+    /// until the job's condition is evaluated.
+    container: Option<github::container::Container>,
+    services: Vec<(String, github::container::Container)>,
+    /// The stage the job runs in, for providers which group jobs into
+    /// stages.
+    pub stage: Option<String>,
+    /// The trigger which caused this job to be selected.
+    pub trigger: Option<Trigger>,
+}
+
+impl Job {
+    /// Returns whether the job should run at all, given the results
+    /// of the jobs which have already run. By default a job only runs
+    /// if all of the jobs it needs succeeded. Needed jobs which were
+    /// not selected to run locally are ignored.
+    pub fn should_run(
+        &mut self,
+        workspace: &Path,
+        results: &HashMap<String, JobStatus>,
+    ) -> Result<bool, ExpressionError> {
+        let needs = self
+            .needs
+            .iter()
+            .filter_map(|need| results.get(need).map(|&result| (need, result)))
+            .collect::<Vec<_>>();
+        // Jobs expanded from a reusable workflow also need the jobs their
+        // callers need, which only decide whether the callers run
+        let status = needs_status(
+            needs
+                .iter()
+                .filter(|(need, _)| !self.calls.iter().any(|call| call.needs().contains(need)))
+                .map(|(_, result)| *result),
+        );
+
+        let needs_context = expr::Value::Object(
+            needs
+                .iter()
+                .map(|(need, result)| {
+                    let value = expr::object(vec![
+                        ("outputs", expr::object(vec![])),
+                        ("result", result.to_string().as_str().into()),
+                    ]);
+                    // Jobs expanded from a reusable workflow have ids prefixed
+                    // with the calling job's, which the workflow doesn't know.
+                    let id = need.rsplit('/').next().unwrap_or(need);
+                    (id.to_string(), value)
+                })
+                .collect(),
+        );
+        for context in self
+            .tasks
+            .iter_mut()
+            .filter_map(|task| task.context.as_mut())
+        {
+            context.values.insert("needs".into(), needs_context.clone());
+        }
+
+        let context = match &mut self.context {
+            Some(context) => context,
+            None => return Ok(status == JobStatus::Success),
+        };
+        context.workspace = workspace.to_path_buf();
+        context.status = status;
+        context.values.insert(
+            "github".into(),
+            github::context::github(self.trigger.as_ref(), workspace),
+        );
+        context.values.insert("needs".into(), needs_context);
+        // Each caller's condition is evaluated in its own context, before
+        // the `inputs` and `secrets` of the workflow it calls replace it
+        let matrix = context.values.get("matrix").cloned();
+        for call in &self.calls {
+            context.values.insert("matrix".into(), call.matrix());
+            context.status = needs_status(
+                needs
+                    .iter()
+                    .filter(|(need, _)| call.needs().contains(need))
+                    .map(|(_, result)| *result),
+            );
+            let caller_runs = match call.condition() {
+                Some(condition) => expr::evaluate_condition(condition, context)?,
+                None => context.status == JobStatus::Success,
+            };
+            if !caller_runs {
+                return Ok(false);
+            }
+            call.bind(context)?;
+        }
+        context.status = status;
+        if let Some(matrix) = matrix {
+            context.values.insert("matrix".into(), matrix);
+        }
+        if !self.calls.is_empty() {
+            for name in &["inputs", "secrets"] {
+                let value = context.values[*name].clone();
+                for task_context in self.tasks.iter_mut().filter_map(|t| t.context.as_mut()) {
+                    task_context
+                        .values
+                        .insert((*name).to_string(), value.clone());
+                }
+            }
+        }
+
+        let context = &*context;
+        let interpolate = |s: &String| expr::interpolate(s, context);
+        self.continue_on_error = self
+            .continue_on_error
+            .as_ref()
+            .map(interpolate)
+            .transpose()?;
+        self.timeout_minutes = self.timeout_minutes.as_ref().map(interpolate).transpose()?;
+        if let Some(minutes) = &self.timeout_minutes {
+            timeout_from(minutes)?;
+        }
+        self.fail_fast = self.fail_fast.as_ref().map(interpolate).transpose()?;
+        self.runs_on = self
+            .runs_on
+            .iter()
+            .map(interpolate)
+            .collect::<Result<_, _>>()?;
+        self.container = self
+            .container
+            .as_ref()
+            .map(|container| container.interpolate(context))
+            .transpose()?;
+        self.services = self
+            .services
+            .iter()
+            .map(|(id, service)| Ok((id.clone(), service.interpolate(context)?)))
+            .collect::<Result<_, ExpressionError>>()?;
+
+        let should_run = match &self.condition {
+            Some(condition) => expr::evaluate_condition(condition, context)?,
+            None => status == JobStatus::Success,
+        };
+
+        // The `strategy` context reports the value the job acts on
+        let fail_fast = expr::Value::Bool(self.fail_fast_enabled());
+        for task_context in self.tasks.iter_mut().filter_map(|t| t.context.as_mut()) {
+            if let Some(expr::Value::Object(strategy)) = task_context.values.get_mut("strategy") {
+                strategy.insert("fail-fast".into(), fail_fast.clone());
+            }
+        }
+
+        Ok(should_run)
+    }
+
+    /// Returns the first of the job's runner labels which means it
+    /// can't run on this machine, given the operating systems of any
+    /// custom labels. The job's condition must be evaluated first.
+    pub fn unsupported_runner(&self, custom_labels: &HashMap<String, String>) -> Option<&str> {
+        github::labels::unsupported(&self.runs_on, custom_labels)
+    }
+
+    /// Returns the container the job's steps run in, if any. The job's
+    /// condition must be evaluated first.
+    pub fn container(&self) -> Option<&github::container::Container> {
+        self.container.as_ref()
+    }
+
+    /// Returns the service containers to start alongside the job, by
+    /// id. The job's condition must be evaluated first.
+    pub fn services(&self) -> &[(String, github::container::Container)] {
+        &self.services
+    }
+
+    /// Returns whether the job comes from a GitHub workflow, so it
+    /// should run in an emulation of the environment GitHub Actions
+    /// gives to jobs.
+    pub fn emulates_github(&self) -> bool {
+        self.context.is_some()
+    }
+
+    /// Adds a value to the expression context of the job and its tasks.
+    fn insert_context(&mut self, name: &str, value: expr::Value) {
+        let contexts = self
+            .tasks
+            .iter_mut()
+            .map(|task| &mut task.context)
+            .chain(std::iter::once(&mut self.context));
+        for context in contexts.flatten() {
+            context.values.insert(name.to_string(), value.clone());
+        }
+    }
+
+    /// Returns whether a failure of the job should be reported
+    /// without failing the run.
+    pub fn continues_on_error(&self) -> bool {
+        is_true(&self.continue_on_error)
+    }
+
+    /// Returns how long the job may run for before it is cancelled.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout_minutes
+            .as_deref()
+            .and_then(|minutes| timeout_from(minutes).ok().flatten())
+    }
+
+    /// Returns whether the job is one combination of a matrix.
+    pub fn is_matrix(&self) -> bool {
+        !self.matrix.is_empty()
+    }
+
+    /// Returns how many combinations of the job's matrix may run at
+    /// once. They run one at a time unless `max-parallel` allows more,
+    /// since running them at once may be too much for this machine.
+    pub fn max_parallel(&self) -> usize {
+        self.max_parallel
+            .as_deref()
+            .and_then(|n| n.trim().parse::<usize>().ok())
+            .filter(|_| self.is_matrix())
+            .unwrap_or(1)
+            .max(1)
+    }
+
+    /// Returns whether a failure of this matrix combination cancels
+    /// the job's other combinations, which is the default.
+    pub fn fails_fast(&self) -> bool {
+        self.is_matrix() && self.fail_fast_enabled()
+    }
+
+    /// Returns whether the job's strategy has `fail-fast` enabled, which
+    /// is the default. The job's condition must be evaluated first.
+    fn fail_fast_enabled(&self) -> bool {
+        self.fail_fast
+            .as_deref()
+            .is_none_or(|fail_fast| fail_fast.trim() != "false")
+    }
+}
+
+fn is_true(value: &Option<String>) -> bool {
+    value.as_deref().map(str::trim) == Some("true")
+}
+
+/// Returns the combined status of the jobs a job needs: failed if any
+/// of them failed, successful if all of them succeeded, and otherwise
+/// skipped.
+fn needs_status(results: impl Iterator<Item = JobStatus>) -> JobStatus {
+    let results = results.collect::<Vec<_>>();
+
+    if results.contains(&JobStatus::Failure) {
+        JobStatus::Failure
+    } else if results.iter().all(|&result| result == JobStatus::Success) {
+        JobStatus::Success
+    } else {
+        JobStatus::Skipped
+    }
+}
+
+/// Parses a `timeout-minutes` value, once any expressions in it have
+/// been evaluated. Returns `None` for timeouts too long to represent,
+/// since they never expire.
+fn timeout_from(minutes: &str) -> Result<Option<Duration>, ExpressionError> {
+    let minutes = minutes
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|minutes| minutes.is_finite() && *minutes >= 0.0)
+        .ok_or_else(|| ExpressionError::new(minutes, "expected a number of minutes"))?;
+
+    Ok(Duration::try_from_secs_f64(minutes * 60.0).ok())
+}
+
+/// JobStatus is the status of a job so far, which decides which of
+/// its remaining tasks should run. Once a job completes it is also
+/// the result seen by the jobs which need it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JobStatus {
+    Success,
+    Failure,
+    Cancelled,
+    Skipped,
+}
+
+impl Display for JobStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            JobStatus::Success => write!(f, "success"),
+            JobStatus::Failure => write!(f, "failure"),
+            JobStatus::Cancelled => write!(f, "cancelled"),
+            JobStatus::Skipped => write!(f, "skipped"),
+        }
+    }
+}
+
+/// Shell describes how to invoke a program to run a task's command.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Shell {
+    /// The program followed by its arguments. Any `{0}` is replaced
+    /// with the path to a script file containing the command.
+    pub args: Vec<String>,
+    /// The extension to give the script file, since some shells
+    /// require a particular one.
+    pub extension: &'static str,
 }
 
 /// Applicability represents the times when a task should be run.
@@ -14,15 +594,30 @@ pub struct Task {
 /// See Trigger for additional details.
 #[derive(Clone)]
 pub enum Applicability {
-    /// This task should only be run on a push. If branches are
-    /// specified it should only be run on a push to these particular
-    /// branches.
+    /// This task should only be run on a push. If branch or tag
+    /// filters are specified it should only be run on a push to
+    /// matching branches or tags, and if path filters are specified
+    /// it should only be run when matching files changed.
     ///
     /// Belay will run these tasks even on pre-commit hooks (as well
     /// as pre-push hooks).
-    Push { branches: Option<Vec<String>> },
-    /// This tasks should be run on pull requests.
-    PullRequest,
+    Push {
+        branches: Filter,
+        tags: Filter,
+        paths: Filter,
+    },
+    /// This tasks should be run on pull requests. The branch filter
+    /// applies to the branch the pull request targets, and if activity
+    /// types are specified it should only be run for those activities.
+    PullRequest {
+        branches: Filter,
+        types: Option<Vec<String>>,
+        paths: Filter,
+    },
+    /// This task should only be run for another event, such as
+    /// `workflow_dispatch` or `schedule`. Since these don't happen
+    /// locally, they have to be simulated with `--event`.
+    Event { name: String },
     /// This task is always applicable.
     Any,
 }
@@ -32,9 +627,31 @@ pub enum Applicability {
 /// Of course we can't know whether the user has an open pull
 /// request (or will open a pull request), but we can assume this
 /// based on whether they have an upstream remote configured.
+///
+/// Each trigger also carries the files which changed, or `None` if
+/// they couldn't be determined, in which case path filters are
+/// ignored.
+#[derive(Clone)]
 pub enum Trigger {
-    Push { branch: String },
-    PullRequest,
+    Push {
+        branch: String,
+        changed_files: Option<Vec<String>>,
+    },
+    PullRequest {
+        /// The branch the pull request probably targets, if it could
+        /// be determined.
+        base_branch: Option<String>,
+        /// The activity which triggers the pull request workflow, such
+        /// as `opened` or `synchronize`.
+        action: String,
+        changed_files: Option<Vec<String>>,
+    },
+    /// Any other event, which is simulated with `--event`.
+    Event {
+        name: String,
+        /// The values of the workflow's inputs given with `--input`.
+        inputs: HashMap<String, String>,
+    },
 }
 
 impl Applicability {
@@ -42,81 +659,391 @@ impl Applicability {
         match (self, trigger) {
             (
                 Applicability::Push {
-                    branches: Some(branches),
+                    branches,
+                    tags,
+                    paths,
                 },
-                Trigger::Push { branch },
-            ) => branches.contains(branch),
-            (Applicability::Push { branches: None }, Trigger::Push { .. }) => true,
-            (Applicability::PullRequest, Trigger::PullRequest) => true,
+                Trigger::Push {
+                    branch,
+                    changed_files,
+                },
+            ) => {
+                // A workflow which only filters tags isn't run for pushes to branches
+                if tags.is_set() && !branches.is_set() {
+                    return false;
+                }
+
+                branches.allows(branch) && paths.allows_any(changed_files.as_deref())
+            }
+            (
+                Applicability::PullRequest {
+                    branches,
+                    types,
+                    paths,
+                },
+                Trigger::PullRequest {
+                    base_branch,
+                    action,
+                    changed_files,
+                },
+            ) => {
+                // Without types, GitHub runs pull request workflows for
+                // `opened`, `synchronize` and `reopened`, which covers
+                // anything pushing can do.
+                let is_type = types.as_ref().is_none_or(|types| types.contains(action));
+                let is_branch = base_branch
+                    .as_ref()
+                    .is_none_or(|base_branch| branches.allows(base_branch));
+
+                is_type && is_branch && paths.allows_any(changed_files.as_deref())
+            }
+            (Applicability::Event { name }, Trigger::Event { name: event, .. }) => name == event,
             (Applicability::Any, _) => true,
             (_, _) => false,
         }
     }
 }
 
+/// MatrixFilter restricts which matrix combinations are run. It is
+/// parsed from `key=value`, and a combination is only run if its
+/// value for `key` matches one of the filters given for that key.
+pub struct MatrixFilter {
+    pub key: String,
+    pub value: String,
+}
+
+impl FromStr for MatrixFilter {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let mut parts = input.splitn(2, '=');
+
+        match (parts.next(), parts.next()) {
+            (Some(key), Some(value)) if !key.is_empty() => Ok(MatrixFilter {
+                key: key.to_string(),
+                value: value.to_string(),
+            }),
+            _ => Err(format!("expected `key=value`, found `{}`", input)),
+        }
+    }
+}
+
+fn is_selected(matrix: &[(String, String)], filters: &[MatrixFilter]) -> bool {
+    matrix.iter().all(|(key, value)| {
+        let mut filters = filters
+            .iter()
+            .filter(|filter| &filter.key == key)
+            .peekable();
+
+        filters.peek().is_none() || filters.any(|filter| &filter.value == value)
+    })
+}
+
 pub trait TaskList {
-    /// Returns all CI tasks, including tasks which we
+    /// Returns all CI jobs, including tasks which we
     /// would not want to execute in belay.
-    fn all_tasks(&self) -> Vec<Task>;
+    fn all_jobs(&self) -> Vec<Job>;
+
+    /// Returns the `inputs` context for a run with the given trigger,
+    /// for providers which support inputs.
+    fn inputs(&self, _trigger: &Trigger) -> Result<Option<expr::Value>, String> {
+        Ok(None)
+    }
 
-    /// Returns the subset of CI tasks that we do
+    /// Returns the subset of CI jobs and tasks that we do
     /// want to execute in belay.
-    fn tasks(&self, config: Config, triggers: Vec<Trigger>) -> Vec<Task> {
-        fn is_applicable(applicabilities: &[Applicability], triggers: &[Trigger]) -> bool {
-            for applicability in applicabilities {
-                for trigger in triggers {
+    fn jobs(
+        &self,
+        config: &Config,
+        triggers: &[Trigger],
+        matrix_filters: &[MatrixFilter],
+    ) -> Result<Vec<Job>, String> {
+        fn applicable_trigger<'a>(
+            applicabilities: &[Applicability],
+            triggers: &'a [Trigger],
+        ) -> Option<&'a Trigger> {
+            for trigger in triggers {
+                for applicability in applicabilities {
                     if applicability.is_triggered_by(trigger) {
-                        return true;
+                        return Some(trigger);
                     }
                 }
             }
 
-            false
+            None
         }
 
-        self.all_tasks()
-            .into_iter()
-            .filter(|task| {
-                for blacklisted_command in &config.command_blacklist {
-                    if task.command.contains(blacklisted_command) {
-                        return false;
-                    }
+        let mut jobs = vec![];
+        for mut job in self.all_jobs() {
+            let trigger = match applicable_trigger(&job.applicability, triggers) {
+                Some(trigger) => trigger,
+                None => continue,
+            };
+            // Jobs expanded from a reusable workflow are also filtered by
+            // the matrix of each job which called it
+            let callers_selected = job
+                .calls
+                .iter()
+                .all(|call| is_selected(&call.matrix_values(), matrix_filters));
+            if !is_selected(&job.matrix, matrix_filters) || !callers_selected {
+                continue;
+            }
+
+            if let Some(inputs) = self.inputs(trigger)? {
+                job.insert_context("inputs", inputs);
+            }
+            job.insert_context(
+                "secrets",
+                expr::Value::Object(
+                    config
+                        .secrets
+                        .iter()
+                        .map(|(k, v)| (k.clone(), v.as_str().into()))
+                        .collect(),
+                ),
+            );
+            job.trigger = Some(trigger.clone());
+            let is_blacklisted = |command: &str| {
+                config
+                    .command_blacklist
+                    .iter()
+                    .any(|blacklisted_command| command.contains(blacklisted_command.as_str()))
+            };
+            job.tasks.retain_mut(|task| match &mut task.script_lines {
+                // Blacklisted lines are left out of a script, rather than
+                // skipping the whole script
+                Some(lines) => {
+                    lines.retain(|line| !is_blacklisted(line));
+                    task.command = gitlab::script(lines);
+                    !lines.is_empty()
                 }
+                None => !is_blacklisted(&task.command),
+            });
 
-                true
-            })
-            .filter(|task| is_applicable(&task.applicability, &triggers))
-            .collect()
+            jobs.push(job);
+        }
+
+        Ok(jobs)
     }
 }
 
 impl TaskList for github::CiConfig {
-    fn all_tasks(&self) -> Vec<Task> {
-        self.jobs
-            .values()
-            .flat_map(|job| &job.steps)
-            .map(|step| Task {
-                name: step.name.clone(),
-                command: step.run.clone(),
-                applicability: self.on.clone(),
-            })
-            .collect()
+    fn inputs(&self, trigger: &Trigger) -> Result<Option<expr::Value>, String> {
+        match trigger {
+            Trigger::Event { name, inputs } if name == "workflow_dispatch" => {
+                github::inputs::context(&self.inputs, inputs).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn all_jobs(&self) -> Vec<Job> {
+        let mut jobs = vec![];
+
+        for job_name in &self.job_order {
+            let job = &self.jobs[job_name];
+            for combination in job.combinations() {
+                let mut tasks = vec![];
+
+                for step in &job.steps {
+                    // Steps which use an action are named after it, as
+                    // they have no command to fall back on.
+                    let step_name = step.name.clone().or_else(|| step.uses.clone());
+                    let name = if combination.is_empty() {
+                        step_name
+                    } else {
+                        // Include the matrix values in the name, so it is clear
+                        // which combination is being run.
+                        let label = github::matrix::label(&combination);
+                        Some(format!(
+                            "{} {}",
+                            step_name.or_else(|| step.run.clone()).unwrap_or_default(),
+                            label
+                        ))
+                    };
+
+                    let skip_reason = match &step.uses {
+                        Some(_) if step.noop => Some("it isn't needed locally".to_string()),
+                        Some(_) if step.run.is_some() => None,
+                        Some(uses) if github::is_local_action(uses) => {
+                            Some("it isn't a composite action".to_string())
+                        }
+                        Some(_) => Some("it uses an action which can't run locally".to_string()),
+                        None => None,
+                    };
+
+                    tasks.push(Task {
+                        id: step.id.clone(),
+                        name,
+                        command: step.run.clone().unwrap_or_default(),
+                        env: self.step_env(job, step),
+                        working_directory: self.step_working_directory(job, step),
+                        shell: Some(self.step_shell(job, step)),
+                        default_shell: self.configured_shell(job, step).is_none(),
+                        skip_reason,
+                        condition: step.condition.clone(),
+                        continue_on_error: step.continue_on_error.clone(),
+                        timeout_minutes: step.timeout_minutes.clone(),
+                        inputs: step.inputs.clone(),
+                        composites: step
+                            .composites
+                            .iter()
+                            .map(|composite| github::action::Composite {
+                                env: self.composite_env(job, composite),
+                                ..composite.clone()
+                            })
+                            .collect(),
+                        context: Some(self.step_context(job, step, &combination)),
+                        script_lines: None,
+                        always: false,
+                    });
+                }
+                if let Some(uses) = &job.uses {
+                    tasks.push(Task {
+                        id: None,
+                        name: Some(uses.clone()),
+                        command: String::new(),
+                        env: HashMap::new(),
+                        working_directory: None,
+                        shell: None,
+                        default_shell: false,
+                        skip_reason: Some(
+                            "it calls a workflow which can't run locally".to_string(),
+                        ),
+                        condition: None,
+                        continue_on_error: None,
+                        timeout_minutes: None,
+                        inputs: vec![],
+                        composites: vec![],
+                        context: None,
+                        script_lines: None,
+                        always: false,
+                    });
+                }
+
+                let name = if combination.is_empty() {
+                    job_name.clone()
+                } else {
+                    format!("{} {}", job_name, github::matrix::label(&combination))
+                };
+
+                jobs.push(Job {
+                    id: job_name.clone(),
+                    name,
+                    tasks,
+                    needs: job.needs.clone(),
+                    condition: job.condition.clone(),
+                    continue_on_error: job.continue_on_error.clone(),
+                    timeout_minutes: job.timeout_minutes.clone(),
+                    context: Some(self.job_context(&combination)),
+                    applicability: self.on.clone(),
+                    matrix: github::matrix::values(&combination),
+                    fail_fast: job.strategy.fail_fast.clone(),
+                    max_parallel: job.strategy.max_parallel.clone(),
+                    calls: job.calls.clone(),
+                    runs_on: job.runs_on.clone(),
+                    container: job.container.clone(),
+                    services: job.services.clone(),
+                    stage: None,
+                    trigger: None,
+                });
+            }
+        }
+
+        jobs
     }
 }
 
 impl TaskList for gitlab::CiConfig {
-    fn all_tasks(&self) -> Vec<Task> {
-        self.jobs
-            .values()
-            .filter_map(|job| job.script.as_ref())
-            .flat_map(|script: &Vec<String>| script)
-            .map(|cmd| Task {
-                name: None,
-                command: cmd.clone(),
-                // For now restricted applicability is not supported
-                // for gitlab within belay.
-                applicability: vec![Applicability::Any],
+    fn all_jobs(&self) -> Vec<Job> {
+        let project = gitlab::Project::local();
+
+        self.ordered_jobs()
+            .into_iter()
+            .map(|(name, job)| {
+                let mut session = self.before_script(job).to_vec();
+                session.extend(job.script.iter().flatten().cloned());
+                let env = self.variables(name, job, &project);
+                let mut tasks = vec![gitlab_task(name.to_string(), session, env.clone())];
+
+                let after_script = self.after_script(job);
+                if !after_script.is_empty() {
+                    let mut task = gitlab_task(
+                        format!("{} (after_script)", name),
+                        after_script.to_vec(),
+                        env,
+                    );
+                    // GitLab runs it even if the job failed, and ignores its result
+                    task.always = true;
+                    task.continue_on_error = Some("true".into());
+                    tasks.push(task);
+                }
+
+                Job {
+                    id: name.to_string(),
+                    name: name.to_string(),
+                    needs: self.needs(name, job),
+                    tasks,
+                    condition: None,
+                    continue_on_error: None,
+                    timeout_minutes: None,
+                    context: None,
+                    // For now restricted applicability is not supported
+                    // for gitlab within belay.
+                    applicability: vec![Applicability::Any],
+                    matrix: vec![],
+                    fail_fast: None,
+                    max_parallel: None,
+                    calls: vec![],
+                    runs_on: vec![],
+                    container: None,
+                    services: vec![],
+                    stage: Some(job.stage().to_string()),
+                    trigger: None,
+                }
             })
             .collect()
     }
 }
+
+/// Creates a task which runs the lines of a GitLab script in one
+/// shell session, with the job's variables in its environment.
+fn gitlab_task(name: String, lines: Vec<String>, env: HashMap<String, String>) -> Task {
+    Task {
+        id: None,
+        name: Some(name),
+        command: gitlab::script(&lines),
+        env,
+        working_directory: None,
+        shell: Some(gitlab::shell()),
+        default_shell: false,
+        skip_reason: None,
+        condition: None,
+        continue_on_error: None,
+        timeout_minutes: None,
+        inputs: vec![],
+        composites: vec![],
+        context: None,
+        script_lines: Some(lines),
+        always: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_timeouts() {
+        assert_eq!(
+            Some(Duration::from_secs(90)),
+            timeout_from(" 1.5 ").unwrap()
+        );
+        // Timeouts too long to represent never expire
+        assert_eq!(None, timeout_from("1e18").unwrap());
+
+        assert!(timeout_from("-1").is_err());
+        assert!(timeout_from("inf").is_err());
+        assert!(timeout_from("soon").is_err());
+    }
+}
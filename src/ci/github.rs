@@ -3,33 +3,478 @@ use std::{
     convert::TryFrom,
     error::Error,
     fmt::{self, Display},
+    path::Path,
 };
 
-use yaml_rust::YamlLoader;
-
-use super::Applicability;
+use yaml_rust::{Yaml, YamlLoader};
+
+use super::{Applicability, Shell};
+use expr::Value;
+use filter::Filter;
+
+pub mod action;
+pub mod call;
+pub mod container;
+pub mod context;
+pub mod expr;
+pub mod filter;
+mod glob;
+pub mod inputs;
+pub mod labels;
+pub mod matrix;
+pub mod shell;
+pub mod shim;
 
 pub struct CiConfig {
     #[allow(dead_code)]
     pub name: String,
     pub jobs: HashMap<String, CiConfigJob>,
+    /// The ids of the jobs in the order they should run, so that
+    /// each job runs after the jobs it `needs`.
+    pub job_order: Vec<String>,
     pub on: Vec<Applicability>,
+    /// The inputs of the `workflow_dispatch` event.
+    pub inputs: Vec<inputs::Input>,
+    /// The inputs of the `workflow_call` event, for a reusable workflow.
+    pub call_inputs: Vec<inputs::Input>,
+    /// The secrets of the `workflow_call` event, for a reusable workflow.
+    pub call_secrets: Vec<call::Secret>,
+    pub env: HashMap<String, String>,
+    pub defaults: RunDefaults,
 }
 
 pub struct CiConfigJob {
     pub steps: Vec<CiConfigJobStep>,
+    /// The labels of the runner the job runs on, which may be
+    /// expressions such as `${{ matrix.os }}`.
+    pub runs_on: Vec<String>,
+    /// The container the job's steps run in.
+    pub container: Option<container::Container>,
+    /// The service containers started alongside the job, by id.
+    pub services: Vec<(String, container::Container)>,
+    /// The `if` condition of the job.
+    pub condition: Option<String>,
+    /// The ids of the jobs which must complete before this one.
+    pub needs: Vec<String>,
+    pub continue_on_error: Option<String>,
+    pub timeout_minutes: Option<String>,
+    pub env: HashMap<String, String>,
+    pub defaults: RunDefaults,
+    pub strategy: Strategy,
+    /// The reusable workflow the job calls instead of running steps.
+    /// Local workflows are replaced by their jobs once expanded.
+    pub uses: Option<String>,
+    /// The inputs given to the reusable workflow the job calls.
+    pub with: HashMap<String, String>,
+    /// The secrets given to the reusable workflow the job calls, or
+    /// `None` if it inherits all of the caller's secrets.
+    pub secrets: Option<HashMap<String, String>>,
+    /// For jobs expanded from reusable workflows, how each workflow
+    /// was called, outermost first.
+    pub calls: Vec<call::Binding>,
+}
+
+/// The `strategy` block of a job.
+pub struct Strategy {
+    /// The expanded matrix combinations. This is empty if the job
+    /// doesn't use a matrix.
+    pub matrix: Vec<matrix::Combination>,
+    /// Whether the remaining combinations are cancelled once one of
+    /// them fails. This may be an expression.
+    pub fail_fast: Option<String>,
+    /// The most combinations which may run at once.
+    pub max_parallel: Option<String>,
+}
+
+impl CiConfigJob {
+    /// Returns the combinations this job should be run with. A job
+    /// without a matrix is run once, with an empty combination.
+    pub fn combinations(&self) -> Vec<matrix::Combination> {
+        if self.strategy.matrix.is_empty() {
+            vec![vec![]]
+        } else {
+            self.strategy.matrix.clone()
+        }
+    }
 }
 
 pub struct CiConfigJobStep {
+    pub id: Option<String>,
     pub name: Option<String>,
-    pub run: String,
+    /// The command to run, or `None` if the step uses an action.
+    pub run: Option<String>,
+    /// The action the step uses, such as `actions/checkout@v2`. Local
+    /// composite actions are replaced by their steps once expanded.
+    pub uses: Option<String>,
+    /// The inputs given to the action the step uses.
+    pub with: HashMap<String, String>,
+    /// Whether the action the step uses isn't needed locally, such as
+    /// checking out the repository.
+    pub noop: bool,
+    /// For steps inlined from composite actions, the inputs of each
+    /// action, outermost first. Each may refer to the inputs of the
+    /// one before it.
+    pub inputs: Vec<HashMap<String, String>>,
+    /// For steps inlined from composite actions, the use of each
+    /// action, outermost first.
+    pub composites: Vec<action::Composite>,
+    pub env: HashMap<String, String>,
+    pub shell: Option<Shell>,
+    pub working_directory: Option<String>,
+    /// The `if` condition of the step.
+    pub condition: Option<String>,
+    pub continue_on_error: Option<String>,
+    pub timeout_minutes: Option<String>,
+}
+
+/// The `defaults.run` block, which can be set for the whole
+/// workflow or for a single job.
+#[derive(Default)]
+pub struct RunDefaults {
+    pub shell: Option<Shell>,
+    pub working_directory: Option<String>,
+}
+
+impl RunDefaults {
+    fn from_yaml(yaml: &Yaml) -> Result<Self, YamlParseError> {
+        let run = &yaml["defaults"]["run"];
+
+        Ok(Self {
+            shell: shell_from(&run["shell"])?,
+            working_directory: run["working-directory"].as_str().map(|s| s.to_string()),
+        })
+    }
+}
+
+pub use action::is_local as is_local_action;
+
+/// The depth at which nested composite actions or reusable workflows
+/// are assumed to use each other in a loop.
+const MAX_DEPTH: usize = 10;
+
+impl CiConfig {
+    /// Replaces steps which use local composite actions with the
+    /// steps of those actions, loaded relative to the repository root.
+    pub fn expand_local_actions(&mut self, root_dir: &Path) -> Result<(), YamlParseError> {
+        for job in self.jobs.values_mut() {
+            let steps = std::mem::take(&mut job.steps);
+            job.steps = action::expand(steps, root_dir)?;
+        }
+
+        Ok(())
+    }
+
+    /// Replaces jobs which call local reusable workflows with the jobs
+    /// of those workflows, loaded relative to the repository root.
+    pub fn expand_reusable_workflows(&mut self, root_dir: &Path) -> Result<(), YamlParseError> {
+        call::expand(self, root_dir)
+    }
+
+    /// Replaces steps which use well-known actions with the local
+    /// commands of their shims.
+    pub fn apply_action_shims(&mut self, shims: &shim::Shims) -> Result<(), YamlParseError> {
+        for step in self.jobs.values_mut().flat_map(|job| job.steps.iter_mut()) {
+            shims.apply(step)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the environment for a step, merging the workflow, job
+    /// and step level `env` blocks. As in GitHub Actions, the most
+    /// specific definition of a variable wins.
+    pub fn step_env(&self, job: &CiConfigJob, step: &CiConfigJobStep) -> HashMap<String, String> {
+        let mut env = self.workflow_env(job);
+        env.extend(job.env.clone());
+        env.extend(step.env.clone());
+
+        env
+    }
+
+    /// Returns the environment of a step which used a composite action,
+    /// for its `if` condition. See `step_env`.
+    pub fn composite_env(
+        &self,
+        job: &CiConfigJob,
+        composite: &action::Composite,
+    ) -> HashMap<String, String> {
+        let mut env = self.workflow_env(job);
+        env.extend(job.env.clone());
+        env.extend(composite.env.clone());
+
+        env
+    }
+
+    /// Returns the shell for a step, falling back to the job and
+    /// then the workflow `defaults.run.shell`, and finally to the
+    /// shell GitHub Actions uses by default.
+    pub fn step_shell(&self, job: &CiConfigJob, step: &CiConfigJobStep) -> Shell {
+        self.configured_shell(job, step)
+            .unwrap_or_else(shell::default)
+    }
+
+    /// Returns the shell set for a step by itself or by the job or
+    /// workflow `defaults`, or `None` if it uses the default shell.
+    pub fn configured_shell(&self, job: &CiConfigJob, step: &CiConfigJobStep) -> Option<Shell> {
+        step.shell
+            .clone()
+            .or_else(|| job.defaults.shell.clone())
+            .or_else(|| self.workflow_defaults(job).shell.clone())
+    }
+
+    /// Returns the working directory for a step, falling back to the
+    /// job and then the workflow `defaults.run.working-directory`.
+    pub fn step_working_directory(
+        &self,
+        job: &CiConfigJob,
+        step: &CiConfigJobStep,
+    ) -> Option<String> {
+        step.working_directory
+            .clone()
+            .or_else(|| job.defaults.working_directory.clone())
+            .or_else(|| self.workflow_defaults(job).working_directory.clone())
+    }
+
+    /// Returns the workflow level `env` for a job. Jobs expanded from a
+    /// reusable workflow already include that workflow's, and don't
+    /// see the caller's.
+    fn workflow_env(&self, job: &CiConfigJob) -> HashMap<String, String> {
+        if job.calls.is_empty() {
+            self.env.clone()
+        } else {
+            HashMap::new()
+        }
+    }
+
+    /// Returns the workflow level `defaults` for a job, see `workflow_env`.
+    fn workflow_defaults(&self, job: &CiConfigJob) -> RunDefaults {
+        if job.calls.is_empty() {
+            RunDefaults {
+                shell: self.defaults.shell.clone(),
+                working_directory: self.defaults.working_directory.clone(),
+            }
+        } else {
+            RunDefaults::default()
+        }
+    }
+
+    /// Returns the expression context for the `if` condition of a job,
+    /// except for the `github` context which depends on how the workflow
+    /// was triggered.
+    pub fn job_context(&self, combination: &matrix::Combination) -> expr::Context {
+        let mut context = expr::Context::new();
+
+        // Only some contexts are available to job conditions, but the
+        // matrix is included since belay expands it before evaluating them.
+        let values = &mut context.values;
+        values.insert("matrix".into(), matrix::context(combination));
+        for name in &["inputs", "needs", "vars"] {
+            values.insert((*name).to_string(), expr::object(vec![]));
+        }
+
+        context
+    }
+
+    /// Returns the expression context for the steps of a job, except
+    /// for the `github` context which depends on how the workflow was
+    /// triggered.
+    pub fn step_context(
+        &self,
+        job: &CiConfigJob,
+        step: &CiConfigJobStep,
+        combination: &matrix::Combination,
+    ) -> expr::Context {
+        let mut context = expr::Context::new();
+
+        let env = self
+            .step_env(job, step)
+            .into_iter()
+            .map(|(k, v)| (k, Value::String(v)))
+            .collect();
+        let combinations = job.combinations();
+        let job_index = combinations
+            .iter()
+            .position(|c| c == combination)
+            .unwrap_or(0);
+        let max_parallel = job
+            .strategy
+            .max_parallel
+            .as_deref()
+            .and_then(|n| n.trim().parse().ok())
+            .unwrap_or(combinations.len() as f64);
+
+        let values = &mut context.values;
+        values.insert("env".into(), Value::Object(env));
+        values.insert("matrix".into(), matrix::context(combination));
+        values.insert("runner".into(), context::runner());
+        values.insert(
+            "strategy".into(),
+            // `fail-fast` may be an expression, so it is added once the
+            // job's condition has been evaluated
+            expr::object(vec![
+                ("job-index", Value::Number(job_index as f64)),
+                ("job-total", Value::Number(combinations.len() as f64)),
+                ("max-parallel", Value::Number(max_parallel)),
+            ]),
+        );
+        values.insert(
+            "job".into(),
+            expr::object(vec![("status", "success".into())]),
+        );
+        for name in &["inputs", "needs", "secrets", "steps", "vars"] {
+            values.insert((*name).to_string(), expr::object(vec![]));
+        }
+
+        context
+    }
+}
+
+/// Converts a scalar yaml value to the string which would be
+/// exposed to a process, so `RUST_BACKTRACE: 1` and
+/// `RUST_BACKTRACE: "1"` are treated the same.
+fn scalar_to_string(yaml: &Yaml) -> Option<String> {
+    match yaml {
+        Yaml::String(s) | Yaml::Real(s) => Some(s.clone()),
+        Yaml::Integer(i) => Some(i.to_string()),
+        Yaml::Boolean(b) => Some(b.to_string()),
+        Yaml::Null => Some(String::new()),
+        _ => None,
+    }
+}
+
+/// Parses an `env` block, which may exist at the workflow, job
+/// or step level, or a `with` block of inputs.
+fn env_from(yaml: &Yaml) -> Result<HashMap<String, String>, YamlParseError> {
+    let mut env = HashMap::new();
+
+    if let Some(hash) = yaml.as_hash() {
+        for (k, v) in hash {
+            let key = k.as_str().ok_or(YamlParseError::MissingField)?;
+            let value = scalar_to_string(v).ok_or(YamlParseError::MissingField)?;
+            env.insert(key.to_string(), value);
+        }
+    }
+
+    Ok(env)
+}
+
+/// Parses a step, returning `None` for steps which neither run a
+/// command nor use an action.
+fn step_from(step: &Yaml) -> Result<Option<CiConfigJobStep>, YamlParseError> {
+    let run = step["run"].as_str().map(|s| (*s).to_string());
+    let uses = step["uses"].as_str().map(|s| (*s).to_string());
+    if run.is_none() && uses.is_none() {
+        return Ok(None);
+    }
+
+    Ok(Some(CiConfigJobStep {
+        id: step["id"].as_str().map(|s| s.to_string()),
+        name: step["name"].as_str().map(|s| (*s).to_string()),
+        run,
+        uses,
+        with: env_from(&step["with"])?,
+        noop: false,
+        inputs: vec![],
+        composites: vec![],
+        env: env_from(&step["env"])?,
+        shell: shell_from(&step["shell"])?,
+        working_directory: step["working-directory"].as_str().map(|s| s.to_string()),
+        condition: scalar_to_string(&step["if"]),
+        continue_on_error: scalar_to_string(&step["continue-on-error"]),
+        timeout_minutes: scalar_to_string(&step["timeout-minutes"]),
+    }))
+}
+
+/// Parses the activity `types` of an event, which may be a single
+/// type or a list.
+fn types_from(yaml: &Yaml) -> Result<Option<Vec<String>>, YamlParseError> {
+    match yaml {
+        Yaml::BadValue => Ok(None),
+        Yaml::String(activity) => Ok(Some(vec![activity.clone()])),
+        Yaml::Array(types) => types
+            .iter()
+            .map(|activity| {
+                activity
+                    .as_str()
+                    .map(str::to_string)
+                    .ok_or(YamlParseError::MissingField)
+            })
+            .collect::<Result<_, _>>()
+            .map(Some),
+        _ => Err(YamlParseError::MissingField),
+    }
+}
+
+/// Parses a `needs` field, which may be a single job id or a list.
+fn needs_from(yaml: &Yaml) -> Result<Vec<String>, YamlParseError> {
+    match yaml {
+        Yaml::BadValue => Ok(vec![]),
+        Yaml::Array(needs) => needs
+            .iter()
+            .map(|need| scalar_to_string(need).ok_or(YamlParseError::MissingField))
+            .collect(),
+        need => Ok(vec![
+            scalar_to_string(need).ok_or(YamlParseError::MissingField)?
+        ]),
+    }
+}
+
+/// Sorts the jobs so each runs after the jobs it needs. Jobs which
+/// could run at the same time keep the order they were declared in.
+fn order_jobs(
+    declared: &[String],
+    jobs: &HashMap<String, CiConfigJob>,
+) -> Result<Vec<String>, YamlParseError> {
+    for job_name in declared {
+        for need in &jobs[job_name].needs {
+            if !jobs.contains_key(need) {
+                return Err(YamlParseError::UnknownNeed {
+                    job: job_name.clone(),
+                    need: need.clone(),
+                });
+            }
+        }
+    }
+
+    let mut ordered: Vec<String> = vec![];
+    let mut remaining = declared.to_vec();
+    while !remaining.is_empty() {
+        let ready = remaining
+            .iter()
+            .position(|job_name| {
+                jobs[job_name]
+                    .needs
+                    .iter()
+                    .all(|need| ordered.contains(need))
+            })
+            .ok_or_else(|| YamlParseError::DependencyCycle(remaining.clone()))?;
+
+        ordered.push(remaining.remove(ready));
+    }
+
+    Ok(ordered)
 }
 
-fn applicability_from(input: &str, branches: Option<Vec<String>>) -> Result<Applicability, ()> {
-    match input {
-        "push" => Ok(Applicability::Push { branches }),
-        "pull_request" => Ok(Applicability::PullRequest),
-        _ => Err(()),
+fn shell_from(yaml: &Yaml) -> Result<Option<Shell>, YamlParseError> {
+    yaml.as_str().map(shell::parse).transpose()
+}
+
+/// Parses the configuration of an event in the `on` block.
+fn applicability_from(event: &str, config: &Yaml) -> Result<Applicability, YamlParseError> {
+    match event {
+        "push" => Ok(Applicability::Push {
+            branches: Filter::from_yaml(config, "branches")?,
+            tags: Filter::from_yaml(config, "tags")?,
+            paths: Filter::from_yaml(config, "paths")?,
+        }),
+        "pull_request" => Ok(Applicability::PullRequest {
+            branches: Filter::from_yaml(config, "branches")?,
+            types: types_from(&config["types"])?,
+            paths: Filter::from_yaml(config, "paths")?,
+        }),
+        event => Ok(Applicability::Event {
+            name: event.to_string(),
+        }),
     }
 }
 
@@ -38,6 +483,14 @@ pub enum YamlParseError {
     ScanError(yaml_rust::scanner::ScanError),
     MissingDocument,
     MissingField,
+    InvalidShell(String),
+    InvalidFilter(String),
+    InvalidInputType(String),
+    UnknownNeed { job: String, need: String },
+    DependencyCycle(Vec<String>),
+    MissingAction(String),
+    MissingWorkflow(String),
+    NestedTooDeeply(String),
 }
 
 impl Display for YamlParseError {
@@ -46,6 +499,30 @@ impl Display for YamlParseError {
             YamlParseError::ScanError(e) => write!(f, "{}", e),
             YamlParseError::MissingDocument => write!(f, "No yaml document found"),
             YamlParseError::MissingField => write!(f, "Missing required field"),
+            YamlParseError::InvalidShell(shell) => {
+                write!(f, "Custom shell '{}' must contain '{{0}}'", shell)
+            }
+            YamlParseError::InvalidFilter(message) => write!(f, "Invalid filter: {}", message),
+            YamlParseError::InvalidInputType(input_type) => {
+                write!(f, "Unknown input type '{}'", input_type)
+            }
+            YamlParseError::UnknownNeed { job, need } => {
+                write!(f, "Job '{}' needs unknown job '{}'", job, need)
+            }
+            YamlParseError::DependencyCycle(jobs) => write!(
+                f,
+                "Unable to order jobs '{}', since their needs form a cycle",
+                jobs.join("', '")
+            ),
+            YamlParseError::MissingAction(path) => {
+                write!(f, "Unable to find 'action.yml' for action '{}'", path)
+            }
+            YamlParseError::MissingWorkflow(path) => {
+                write!(f, "Unable to find reusable workflow '{}'", path)
+            }
+            YamlParseError::NestedTooDeeply(path) => {
+                write!(f, "'{}' is nested too deeply, it may use itself", path)
+            }
         }
     }
 }
@@ -66,7 +543,9 @@ impl TryFrom<&str> for CiConfig {
             .pop()
             .ok_or(YamlParseError::MissingDocument)?;
 
-        let name = &yaml["name"].as_str().ok_or(YamlParseError::MissingField)?;
+        // Workflows aren't required to have a name, which is common for
+        // reusable workflows
+        let name = yaml["name"].as_str().unwrap_or_default();
 
         let jobs = &yaml["jobs"].as_hash().ok_or(YamlParseError::MissingField)?;
 
@@ -76,70 +555,80 @@ impl TryFrom<&str> for CiConfig {
         // in practice there won't be any meaningful performance difference.
         let on_as_vec = yaml["on"].as_vec().map(|a| {
             a.iter()
-                .filter_map(|item| {
-                    item.as_str()
-                        .iter()
-                        .filter_map(|&s| applicability_from(s, None).ok())
-                        .next()
-                })
-                .collect()
+                .filter_map(|item| item.as_str())
+                .map(|s| applicability_from(s, &Yaml::BadValue))
+                .collect::<Result<Vec<_>, _>>()
         });
         let on_as_map = yaml["on"].as_hash().map(|hashmap| {
             hashmap
                 .iter()
                 .filter_map(|(k, v)| k.as_str().map(|k| (k, v)))
-                .filter_map(|(k, v)| {
-                    let branches = v["branches"].as_vec().map(|branches| {
-                        branches
-                            .iter()
-                            .filter_map(|branch| branch.as_str().map(|s| s.to_string()))
-                            .collect()
-                    });
-                    applicability_from(k, branches).ok()
-                })
-                .collect()
+                .map(|(k, v)| applicability_from(k, v))
+                .collect::<Result<Vec<_>, _>>()
         });
         let on = match (on_as_vec, on_as_map) {
-            (Some(x), _) => x,
-            (_, Some(x)) => x,
+            (Some(x), _) => x?,
+            (_, Some(x)) => x?,
             (None, None) => vec![],
         };
 
         let mut ci_config = CiConfig {
-            name: (*name).to_string(),
+            name: name.to_string(),
             jobs: HashMap::new(),
+            job_order: vec![],
             on,
+            inputs: inputs::parse(&yaml["on"]["workflow_dispatch"]["inputs"])?,
+            call_inputs: inputs::parse(&yaml["on"]["workflow_call"]["inputs"])?,
+            call_secrets: call::parse_secrets(&yaml["on"]["workflow_call"]["secrets"])?,
+            env: env_from(&yaml["env"])?,
+            defaults: RunDefaults::from_yaml(&yaml)?,
         };
 
+        let mut declared = vec![];
         for (job_name, job) in jobs.iter() {
             let job_name = job_name
                 .as_str()
                 .ok_or(YamlParseError::MissingField)
                 .map(|s| (*s).to_string())?;
-
-            let steps = &job["steps"].as_vec().ok_or(YamlParseError::MissingField)?;
-
-            let mut parsed_steps = vec![];
-
-            for step in steps.iter() {
-                let name = step["name"].as_str().map(|s| (*s).to_string());
-                let run = step["run"].as_str().map(|s| (*s).to_string());
-
-                // we skip steps without run
-                if let Some(run) = run {
-                    let step = CiConfigJobStep { name, run };
-
-                    parsed_steps.push(step);
-                }
-            }
+            declared.push(job_name.clone());
+
+            // A job which calls a reusable workflow has no steps of its own
+            let uses = job["uses"].as_str().map(|s| s.to_string());
+            let parsed_steps = match job["steps"].as_vec() {
+                Some(steps) => steps
+                    .iter()
+                    .filter_map(|step| step_from(step).transpose())
+                    .collect::<Result<Vec<_>, _>>()?,
+                None if uses.is_some() => vec![],
+                None => return Err(YamlParseError::MissingField),
+            };
 
             ci_config.jobs.insert(
                 job_name,
                 CiConfigJob {
                     steps: parsed_steps,
+                    runs_on: labels::parse(&job["runs-on"])?,
+                    container: container::Container::from_yaml(&job["container"])?,
+                    services: container::services_from(&job["services"])?,
+                    condition: scalar_to_string(&job["if"]),
+                    needs: needs_from(&job["needs"])?,
+                    continue_on_error: scalar_to_string(&job["continue-on-error"]),
+                    timeout_minutes: scalar_to_string(&job["timeout-minutes"]),
+                    env: env_from(&job["env"])?,
+                    defaults: RunDefaults::from_yaml(job)?,
+                    strategy: Strategy {
+                        matrix: matrix::parse(&job["strategy"]["matrix"])?,
+                        fail_fast: scalar_to_string(&job["strategy"]["fail-fast"]),
+                        max_parallel: scalar_to_string(&job["strategy"]["max-parallel"]),
+                    },
+                    uses,
+                    with: env_from(&job["with"])?,
+                    secrets: call::secrets_from(&job["secrets"])?,
+                    calls: vec![],
                 },
             );
         }
+        ci_config.job_order = order_jobs(&declared, &ci_config.jobs)?;
 
         Ok(ci_config)
     }
@@ -148,6 +637,7 @@ impl TryFrom<&str> for CiConfig {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ci::Trigger;
 
     type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
@@ -163,14 +653,48 @@ mod tests {
 
         let job = &github_ci_config.jobs["build"];
 
-        // the `uses` step is skipped during parsing
-        assert_eq!(5, job.steps.len());
+        // the `uses` step is parsed, so it can be reported as skipped
+        assert_eq!(6, job.steps.len());
+        assert_eq!(Some("actions/checkout@v1"), job.steps[0].uses.as_deref());
+        assert_eq!(None, job.steps[0].run);
 
         assert_eq!(2, github_ci_config.on.len());
 
         Ok(())
     }
 
+    #[test]
+    fn parse_github_yaml_push_to_branch_glob() -> Result<()> {
+        let github_yaml = include_str!("../../tests/github_parse_check_on_push_to_branch_glob.yml");
+
+        let is_triggered_by = |github_yaml: &str, branch: &str| -> Result<bool> {
+            let github_ci_config = CiConfig::try_from(github_yaml)?;
+            let trigger = Trigger::Push {
+                branch: branch.to_string(),
+                changed_files: None,
+            };
+
+            Ok(github_ci_config
+                .on
+                .iter()
+                .any(|applicability| applicability.is_triggered_by(&trigger)))
+        };
+
+        assert!(is_triggered_by(github_yaml, "release/1.0")?);
+        assert!(is_triggered_by(github_yaml, "release/v2/1.0")?);
+        assert!(!is_triggered_by(github_yaml, "release/1.0-alpha")?);
+        assert!(!is_triggered_by(github_yaml, "master")?);
+
+        // Only filtering tags means pushes to branches don't run the workflow
+        let tags_only = github_yaml
+            .replace("- 'release/**'", "")
+            .replace("- '!release/**-alpha'", "")
+            .replace("branches:\n", "");
+        assert!(!is_triggered_by(&tags_only, "release/1.0")?);
+
+        Ok(())
+    }
+
     #[test]
     fn parse_github_yaml_push_to_branch() -> Result<()> {
         let github_yaml = include_str!("../../tests/github_parse_check_on_push_to_branch.yml");
@@ -181,4 +705,174 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn parse_github_yaml_pull_request() -> Result<()> {
+        let github_yaml = include_str!("../../tests/github_pull_request_integration_test.yml");
+
+        let github_ci_config = CiConfig::try_from(github_yaml)?;
+        let is_triggered_by = |base_branch: Option<&str>, action: &str| {
+            let trigger = Trigger::PullRequest {
+                base_branch: base_branch.map(str::to_string),
+                action: action.to_string(),
+                changed_files: None,
+            };
+
+            github_ci_config.on[0].is_triggered_by(&trigger)
+        };
+
+        assert!(is_triggered_by(Some("main"), "opened"));
+        assert!(is_triggered_by(Some("release/1.0"), "synchronize"));
+        assert!(!is_triggered_by(Some("develop"), "opened"));
+        assert!(!is_triggered_by(Some("main"), "closed"));
+        // If the base branch isn't known, the branch filter is ignored
+        assert!(is_triggered_by(None, "opened"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_github_yaml_env() -> Result<()> {
+        let github_yaml = include_str!("../../tests/github_env_integration_test.yml");
+
+        let github_ci_config = CiConfig::try_from(github_yaml)?;
+
+        assert_eq!(".", &github_ci_config.env["PUNCTUATION"]);
+
+        let job = &github_ci_config.jobs["build"];
+        let env = github_ci_config.step_env(job, &job.steps[0]);
+        assert_eq!("hello", &env["GREETING"]);
+        assert_eq!("job", &env["TARGET"]);
+        assert_eq!("!", &env["PUNCTUATION"]);
+
+        let env = github_ci_config.step_env(job, &job.steps[1]);
+        assert_eq!("step", &env["TARGET"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_github_yaml_matrix() -> Result<()> {
+        let github_yaml = include_str!("../../tests/github_matrix_integration_test.yml");
+
+        let github_ci_config = CiConfig::try_from(github_yaml)?;
+
+        let job = &github_ci_config.jobs["test"];
+        assert_eq!(4, job.strategy.matrix.len());
+        assert_eq!(1, github_ci_config.jobs["lint"].combinations().len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_github_yaml_run_defaults() -> Result<()> {
+        let github_yaml = include_str!("../../tests/github_working_directory_integration_test.yml");
+
+        let github_ci_config = CiConfig::try_from(github_yaml)?;
+
+        let job = &github_ci_config.jobs["build"];
+        assert_eq!(
+            Some("backend"),
+            github_ci_config
+                .step_working_directory(job, &job.steps[0])
+                .as_deref()
+        );
+        assert_eq!(
+            Some("frontend"),
+            github_ci_config
+                .step_working_directory(job, &job.steps[1])
+                .as_deref()
+        );
+        assert_eq!(
+            shell::default(),
+            github_ci_config.step_shell(job, &job.steps[0])
+        );
+
+        let job = &github_ci_config.jobs["docs"];
+        assert_eq!(
+            Some("docs"),
+            github_ci_config
+                .step_working_directory(job, &job.steps[0])
+                .as_deref()
+        );
+        assert_eq!(
+            shell::parse("bash")?,
+            github_ci_config.step_shell(job, &job.steps[0])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_github_yaml_reusable_workflow() -> Result<()> {
+        let github_yaml = include_str!("../../tests/github_reusable_workflow_integration_test.yml");
+
+        let github_ci_config = CiConfig::try_from(github_yaml)?;
+
+        let checks = &github_ci_config.jobs["checks"];
+        assert!(checks.steps.is_empty());
+        assert_eq!(
+            Some("./.github/workflows/common.yml"),
+            checks.uses.as_deref()
+        );
+        assert_eq!("${{ matrix.toolchain }}", checks.with["toolchain"]);
+        assert_eq!(2, checks.combinations().len());
+        assert_eq!(
+            "${{ secrets.DEPLOY_TOKEN }}",
+            checks.secrets.as_ref().unwrap()["token"]
+        );
+
+        let reusable_yaml = include_str!("../../tests/github_reusable_workflow.yml");
+        let reusable = CiConfig::try_from(reusable_yaml)?;
+        assert_eq!(
+            vec!["toolchain", "verbose"],
+            reusable
+                .call_inputs
+                .iter()
+                .map(|input| input.id.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![call::Secret {
+                id: "token".into(),
+                required: true
+            }],
+            reusable.call_secrets
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_github_yaml_needs() -> Result<()> {
+        let github_yaml = include_str!("../../tests/github_needs_integration_test.yml");
+
+        let github_ci_config = CiConfig::try_from(github_yaml)?;
+
+        assert_eq!(
+            vec!["build", "test", "deploy-docs", "report"],
+            github_ci_config.job_order
+        );
+        assert_eq!(vec!["build"], github_ci_config.jobs["test"].needs);
+
+        let unknown = github_yaml.replace("needs: build", "needs: compile");
+        assert_eq!(
+            "Job 'test' needs unknown job 'compile'",
+            CiConfig::try_from(unknown.as_str())
+                .err()
+                .unwrap()
+                .to_string()
+        );
+
+        let cycle = github_yaml.replace("needs: build", "needs: report");
+        assert_eq!(
+            "Unable to order jobs 'deploy-docs', 'test', 'report', since their needs form a cycle",
+            CiConfig::try_from(cycle.as_str())
+                .err()
+                .unwrap()
+                .to_string()
+        );
+
+        Ok(())
+    }
 }
@@ -0,0 +1,285 @@
+//! Local actions, which a step references by path with a `uses` such as
+//! `./.github/actions/setup`. Only composite actions can be run, by
+//! inlining their steps into the job which uses them.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use yaml_rust::YamlLoader;
+
+use super::{inputs, step_from, CiConfigJobStep, YamlParseError, MAX_DEPTH};
+
+/// Action is the definition of an action, from its `action.yml`.
+pub struct Action {
+    pub inputs: Vec<inputs::Input>,
+    /// The outputs of the action, as expressions over the outputs of
+    /// its steps.
+    pub outputs: HashMap<String, String>,
+    /// The steps of a composite action, or `None` for the JavaScript
+    /// and Docker actions which can't be run locally.
+    pub steps: Option<Vec<CiConfigJobStep>>,
+}
+
+impl Action {
+    /// Loads the action at the path relative to the repository root.
+    pub fn load(root_dir: &Path, path: &str) -> Result<Self, YamlParseError> {
+        let dir = root_dir.join(path);
+        let file = ["action.yml", "action.yaml"]
+            .iter()
+            .map(|name| dir.join(name))
+            .find(|file| file.is_file())
+            .ok_or_else(|| YamlParseError::MissingAction(path.to_string()))?;
+        let input = fs::read_to_string(file)
+            .map_err(|_| YamlParseError::MissingAction(path.to_string()))?;
+        let yaml = YamlLoader::load_from_str(&input)?
+            .pop()
+            .ok_or(YamlParseError::MissingDocument)?;
+
+        let outputs = match yaml["outputs"].as_hash() {
+            Some(outputs) => outputs
+                .iter()
+                .filter_map(|(id, output)| {
+                    Some((
+                        id.as_str()?.to_string(),
+                        output["value"].as_str()?.to_string(),
+                    ))
+                })
+                .collect(),
+            None => HashMap::new(),
+        };
+        let steps = match yaml["runs"]["using"].as_str() {
+            Some("composite") => Some(
+                yaml["runs"]["steps"]
+                    .as_vec()
+                    .ok_or(YamlParseError::MissingField)?
+                    .iter()
+                    .filter_map(|step| step_from(step).transpose())
+                    .collect::<Result<_, _>>()?,
+            ),
+            _ => None,
+        };
+
+        Ok(Self {
+            inputs: inputs::parse(&yaml["inputs"])?,
+            outputs,
+            steps,
+        })
+    }
+
+    /// Returns the values of the inputs for a step which uses the
+    /// action, from its `with` block or else the defaults.
+    fn bind_inputs(&self, with: &HashMap<String, String>) -> HashMap<String, String> {
+        self.inputs
+            .iter()
+            .map(|input| {
+                let value = with
+                    .get(&input.id)
+                    .or(input.default.as_ref())
+                    .cloned()
+                    .unwrap_or_default();
+
+                (input.id.clone(), value)
+            })
+            .collect()
+    }
+}
+
+/// Composite is a use of a composite action which a step was inlined
+/// from. The step which used the action is run as a whole: its `if`
+/// condition decides whether any of the action's steps run, and once
+/// they have, the action's outputs are recorded under its `id`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Composite {
+    /// Tells apart uses of the action, since the same action may be
+    /// used by consecutive steps.
+    pub key: usize,
+    pub id: Option<String>,
+    /// The name of the step, or else the action it uses.
+    pub name: String,
+    /// The `if` condition of the step.
+    pub condition: Option<String>,
+    /// The `env` of the step, which its condition may refer to.
+    pub env: HashMap<String, String>,
+    /// The outputs of the action, as expressions over its steps.
+    pub outputs: HashMap<String, String>,
+    /// How many of the `inputs` of the inlined steps are the step's,
+    /// rather than the action's.
+    pub caller_inputs: usize,
+}
+
+/// Returns whether a `uses` refers to an action in the repository,
+/// rather than one from the marketplace or a Docker image.
+pub fn is_local(uses: &str) -> bool {
+    uses.starts_with("./")
+}
+
+/// Replaces each step which uses a local composite action with the
+/// steps of that action. Steps which use other actions are kept, so
+/// they can be reported as skipped.
+pub fn expand(
+    steps: Vec<CiConfigJobStep>,
+    root_dir: &Path,
+) -> Result<Vec<CiConfigJobStep>, YamlParseError> {
+    expand_at_depth(steps, root_dir, 0)
+}
+
+/// Counts the uses of composite actions, to give each a unique key.
+static USE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+fn expand_at_depth(
+    steps: Vec<CiConfigJobStep>,
+    root_dir: &Path,
+    depth: usize,
+) -> Result<Vec<CiConfigJobStep>, YamlParseError> {
+    let mut expanded = vec![];
+
+    for step in steps {
+        let path = match step.uses.as_deref() {
+            Some(uses) if is_local(uses) => uses.to_string(),
+            _ => {
+                expanded.push(step);
+                continue;
+            }
+        };
+        if depth >= MAX_DEPTH {
+            return Err(YamlParseError::NestedTooDeeply(path));
+        }
+
+        let action = Action::load(root_dir, &path)?;
+        let mut inputs = step.inputs.clone();
+        inputs.push(action.bind_inputs(&step.with));
+        let action_steps = match action.steps {
+            Some(steps) => steps,
+            None => {
+                expanded.push(step);
+                continue;
+            }
+        };
+        let action_path = root_dir.join(&path).to_string_lossy().into_owned();
+        let composite = Composite {
+            key: USE_COUNT.fetch_add(1, Ordering::SeqCst),
+            id: step.id.clone(),
+            name: step.name.clone().unwrap_or(path),
+            condition: step.condition.clone(),
+            env: step.env.clone(),
+            outputs: action.outputs,
+            caller_inputs: step.inputs.len(),
+        };
+
+        for inner in expand_at_depth(action_steps, root_dir, depth + 1)? {
+            expanded.push(inline(&step, inner, &inputs, &composite, &action_path));
+        }
+    }
+
+    Ok(expanded)
+}
+
+/// Combines a step of a composite action with the step which uses the
+/// action. The outer step's `env` applies to each inner step, while its
+/// `if` is evaluated once for the whole action, see `Composite`.
+fn inline(
+    outer: &CiConfigJobStep,
+    inner: CiConfigJobStep,
+    inputs: &[HashMap<String, String>],
+    composite: &Composite,
+    action_path: &str,
+) -> CiConfigJobStep {
+    let mut outer_env = outer.env.clone();
+    outer_env.insert("GITHUB_ACTION_PATH".into(), action_path.to_string());
+    let mut env = outer_env.clone();
+    env.extend(inner.env);
+
+    // An inner step which was itself inlined from a nested action
+    // carries that action's inputs and use, which follow the outer ones
+    let mut step_inputs = inputs.to_vec();
+    step_inputs.extend(inner.inputs);
+    let mut composites = outer.composites.clone();
+    composites.push(composite.clone());
+    composites.extend(inner.composites.into_iter().map(|mut nested| {
+        let mut env = outer_env.clone();
+        env.extend(nested.env);
+        nested.env = env;
+        nested.caller_inputs += inputs.len();
+        nested
+    }));
+
+    CiConfigJobStep {
+        id: inner.id,
+        name: inner.name,
+        run: inner.run,
+        uses: inner.uses,
+        with: inner.with,
+        noop: inner.noop,
+        inputs: step_inputs,
+        composites,
+        env,
+        shell: inner.shell,
+        working_directory: inner.working_directory,
+        condition: inner.condition,
+        continue_on_error: inner
+            .continue_on_error
+            .or_else(|| outer.continue_on_error.clone()),
+        timeout_minutes: inner
+            .timeout_minutes
+            .or_else(|| outer.timeout_minutes.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use yaml_rust::YamlLoader;
+
+    fn step(yaml: &str) -> CiConfigJobStep {
+        let yaml = YamlLoader::load_from_str(yaml).unwrap().pop().unwrap();
+
+        step_from(&yaml).unwrap().unwrap()
+    }
+
+    #[test]
+    fn inline_step() {
+        let outer = step(
+            r#"
+uses: ./greet
+if: ${{ github.event_name == 'push' }}
+env: { WHO: world, LOUD: 'false' }
+"#,
+        );
+        let inner = step(
+            r#"
+run: echo hello
+if: inputs.loud
+env: { LOUD: 'true' }
+"#,
+        );
+        let inputs = vec![vec![("loud".to_string(), "true".to_string())]
+            .into_iter()
+            .collect()];
+
+        let composite = Composite {
+            key: 0,
+            id: Some("greet".into()),
+            name: "./greet".into(),
+            condition: outer.condition.clone(),
+            env: outer.env.clone(),
+            outputs: HashMap::new(),
+            caller_inputs: 0,
+        };
+
+        let inlined = inline(&outer, inner, &inputs, &composite, "/repo/greet");
+
+        assert_eq!(Some("echo hello"), inlined.run.as_deref());
+        // The outer condition is evaluated once for the whole action
+        assert_eq!(Some("inputs.loud"), inlined.condition.as_deref());
+        assert_eq!(vec![composite], inlined.composites);
+        assert_eq!("world", inlined.env["WHO"]);
+        assert_eq!("true", inlined.env["LOUD"]);
+        assert_eq!("/repo/greet", inlined.env["GITHUB_ACTION_PATH"]);
+        assert_eq!(inputs, inlined.inputs);
+    }
+}
@@ -0,0 +1,296 @@
+//! Reusable workflows, which a job calls by path with a `uses` such as
+//! `./.github/workflows/common.yml`. The jobs of the called workflow are
+//! expanded into the calling workflow, in place of the calling job.
+
+use std::{collections::HashMap, convert::TryFrom, fs, path::Path};
+
+use yaml_rust::Yaml;
+
+use super::{
+    env_from,
+    expr::{self, ExpressionError, Value},
+    inputs, matrix, order_jobs, CiConfig, CiConfigJob, RunDefaults, YamlParseError, MAX_DEPTH,
+};
+
+/// Secret is the definition of a secret a reusable workflow accepts.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Secret {
+    pub id: String,
+    pub required: bool,
+}
+
+/// Parses the `secrets` block of the `workflow_call` event.
+pub fn parse_secrets(yaml: &Yaml) -> Result<Vec<Secret>, YamlParseError> {
+    let secrets = match yaml.as_hash() {
+        Some(secrets) => secrets,
+        None => return Ok(vec![]),
+    };
+
+    secrets
+        .iter()
+        .map(|(id, secret)| {
+            Ok(Secret {
+                id: id.as_str().ok_or(YamlParseError::MissingField)?.to_string(),
+                required: secret["required"].as_bool().unwrap_or(false),
+            })
+        })
+        .collect()
+}
+
+/// Parses the `secrets` given to a reusable workflow by the calling
+/// job, returning `None` if the job passes on all of its secrets
+/// with `secrets: inherit`.
+pub fn secrets_from(yaml: &Yaml) -> Result<Option<HashMap<String, String>>, YamlParseError> {
+    match yaml.as_str() {
+        Some("inherit") => Ok(None),
+        _ => env_from(yaml).map(Some),
+    }
+}
+
+/// Binding is how a job from a reusable workflow was called, which
+/// decides its `inputs` and `secrets` contexts.
+#[derive(Clone)]
+pub struct Binding {
+    /// The path of the called workflow.
+    uses: String,
+    /// The inputs the called workflow accepts.
+    inputs: Vec<inputs::Input>,
+    /// The secrets the called workflow accepts.
+    secrets: Vec<Secret>,
+    /// The values given to the inputs, which may be expressions
+    /// evaluated in the calling job's context.
+    with: HashMap<String, String>,
+    /// The values given to the secrets, or `None` if the calling job
+    /// passes on its own.
+    given_secrets: Option<HashMap<String, String>>,
+    /// The calling job's condition, evaluated in its own context.
+    condition: Option<String>,
+    /// The jobs the calling job needs, which decide whether it runs.
+    needs: Vec<String>,
+    /// The combination of the calling job's matrix the called jobs
+    /// were expanded for, which is empty if it has no matrix.
+    combination: matrix::Combination,
+}
+
+impl Binding {
+    /// Returns the calling job's `if` condition, if it has one.
+    pub fn condition(&self) -> Option<&str> {
+        self.condition.as_deref()
+    }
+
+    /// Returns the jobs the calling job needs.
+    pub fn needs(&self) -> &[String] {
+        &self.needs
+    }
+
+    /// Returns the calling job's `matrix` context.
+    pub fn matrix(&self) -> Value {
+        matrix::context(&self.combination)
+    }
+
+    /// Returns the calling job's matrix values, for matching against
+    /// the filters given by the user.
+    pub fn matrix_values(&self) -> Vec<(String, String)> {
+        matrix::values(&self.combination)
+    }
+
+    /// Replaces the `inputs` and `secrets` in the context with those
+    /// the called workflow receives.
+    pub fn bind(&self, context: &mut expr::Context) -> Result<(), ExpressionError> {
+        let error = |message: String| ExpressionError::new(&self.uses, message);
+
+        let with = self
+            .with
+            .iter()
+            .map(|(k, v)| Ok((k.clone(), expr::interpolate(v, context)?)))
+            .collect::<Result<_, ExpressionError>>()?;
+        let inputs = inputs::context(&self.inputs, &with).map_err(error)?;
+
+        let secrets = match &self.given_secrets {
+            Some(given) => Value::Object(
+                given
+                    .iter()
+                    .map(|(k, v)| Ok((k.clone(), expr::interpolate(v, context)?.as_str().into())))
+                    .collect::<Result<_, ExpressionError>>()?,
+            ),
+            None => context
+                .values
+                .get("secrets")
+                .cloned()
+                .unwrap_or_else(|| expr::object(vec![])),
+        };
+        for secret in self.secrets.iter().filter(|secret| secret.required) {
+            if secrets.get(&secret.id.as_str().into()) == Value::Null {
+                return Err(error(format!("Missing required secret '{}'", secret.id)));
+            }
+        }
+
+        context.values.insert("inputs".into(), inputs);
+        context.values.insert("secrets".into(), secrets);
+
+        Ok(())
+    }
+}
+
+/// Returns whether a job's `uses` refers to a workflow in the
+/// repository, rather than one in another repository.
+pub fn is_local(uses: &str) -> bool {
+    uses.starts_with("./")
+}
+
+/// Replaces each job which calls a local reusable workflow with the
+/// jobs of that workflow. Jobs which call workflows in other
+/// repositories are kept, so they can be reported as skipped.
+pub fn expand(config: &mut CiConfig, root_dir: &Path) -> Result<(), YamlParseError> {
+    expand_at_depth(config, root_dir, 0)
+}
+
+fn expand_at_depth(
+    config: &mut CiConfig,
+    root_dir: &Path,
+    depth: usize,
+) -> Result<(), YamlParseError> {
+    let mut order = vec![];
+    // The jobs which replace each calling job, so jobs which need it
+    // can wait for all of them instead.
+    let mut replaced = HashMap::new();
+
+    for id in std::mem::take(&mut config.job_order) {
+        let path = match config.jobs[&id].uses.as_deref() {
+            Some(uses) if is_local(uses) => uses.to_string(),
+            _ => {
+                order.push(id);
+                continue;
+            }
+        };
+        if depth >= MAX_DEPTH {
+            return Err(YamlParseError::NestedTooDeeply(path));
+        }
+
+        let input = fs::read_to_string(root_dir.join(&path))
+            .map_err(|_| YamlParseError::MissingWorkflow(path.clone()))?;
+        let caller = config.jobs.remove(&id).expect("ordered jobs exist");
+
+        // The workflow is called once for each combination of the
+        // caller's matrix, like the jobs of any other matrix
+        let mut ids = vec![];
+        for combination in caller.combinations() {
+            let mut called = CiConfig::try_from(input.as_str())?;
+            called.expand_local_actions(root_dir)?;
+            expand_at_depth(&mut called, root_dir, depth + 1)?;
+
+            let prefix = if combination.is_empty() {
+                id.clone()
+            } else {
+                format!("{} {}", id, matrix::label(&combination))
+            };
+            let binding = Binding {
+                uses: path.clone(),
+                inputs: called.call_inputs.clone(),
+                secrets: called.call_secrets.clone(),
+                with: caller.with.clone(),
+                given_secrets: caller.secrets.clone(),
+                condition: caller.condition.clone(),
+                needs: caller.needs.clone(),
+                combination: combination.clone(),
+            };
+
+            for called_id in &called.job_order {
+                let job = called.jobs.remove(called_id).expect("ordered jobs exist");
+                let expanded_id = format!("{}/{}", prefix, called_id);
+                config.jobs.insert(
+                    expanded_id.clone(),
+                    inline(&prefix, &caller, &called, job, &binding),
+                );
+                ids.push(expanded_id);
+            }
+        }
+        order.extend(ids.iter().cloned());
+        replaced.insert(id, ids);
+    }
+
+    let replace = |needs: &[String]| {
+        needs
+            .iter()
+            .flat_map(|need| {
+                replaced
+                    .get(need)
+                    .cloned()
+                    .unwrap_or_else(|| vec![need.clone()])
+            })
+            .collect::<Vec<_>>()
+    };
+    for job in config.jobs.values_mut() {
+        job.needs = replace(&job.needs);
+        for call in &mut job.calls {
+            call.needs = replace(&call.needs);
+        }
+    }
+    config.job_order = order_jobs(&order, &config.jobs)?;
+
+    Ok(())
+}
+
+/// Combines a job of a called workflow with the job which called it,
+/// naming the jobs it needs with the prefix of its id. The called job keeps the `env` and `defaults` of its own workflow,
+/// rather than those of the caller, and its own condition. The
+/// caller's condition is kept in the binding, since it is evaluated
+/// against the caller's `inputs` and `secrets`.
+fn inline(
+    prefix: &str,
+    caller: &CiConfigJob,
+    called: &CiConfig,
+    job: CiConfigJob,
+    binding: &Binding,
+) -> CiConfigJob {
+    let mut needs = job
+        .needs
+        .iter()
+        .map(|need| format!("{}/{}", prefix, need))
+        .collect::<Vec<_>>();
+    needs.extend(caller.needs.iter().cloned());
+
+    // Jobs expanded from a nested call already include the `env` and
+    // `defaults` of their own workflow
+    let workflow_defaults = called.workflow_defaults(&job);
+    let mut env = called.workflow_env(&job);
+    env.extend(job.env);
+
+    let mut calls = vec![binding.clone()];
+    // Nested calls refer to the jobs of the called workflow, which are
+    // renamed like its jobs' needs
+    calls.extend(job.calls.into_iter().map(|call| {
+        Binding {
+            needs: call
+                .needs
+                .iter()
+                .map(|need| format!("{}/{}", prefix, need))
+                .collect(),
+            ..call
+        }
+    }));
+
+    CiConfigJob {
+        steps: job.steps,
+        runs_on: job.runs_on,
+        container: job.container,
+        services: job.services,
+        condition: job.condition,
+        needs,
+        continue_on_error: job.continue_on_error,
+        timeout_minutes: job.timeout_minutes,
+        env,
+        defaults: RunDefaults {
+            shell: job.defaults.shell.or(workflow_defaults.shell),
+            working_directory: job
+                .defaults
+                .working_directory
+                .or(workflow_defaults.working_directory),
+        },
+        strategy: job.strategy,
+        uses: job.uses,
+        with: job.with,
+        secrets: job.secrets,
+        calls,
+    }
+}
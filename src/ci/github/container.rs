@@ -0,0 +1,153 @@
+//! The `container` and `services` of a job, which run its steps in a
+//! container and start sidecar containers alongside it.
+
+use std::collections::HashMap;
+
+use yaml_rust::Yaml;
+
+use super::{
+    env_from,
+    expr::{self, ExpressionError},
+    scalar_to_string, YamlParseError,
+};
+
+/// Container is the configuration of a container for a job or one of
+/// its services. Its fields may be expressions until interpolated.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Container {
+    pub image: String,
+    pub env: HashMap<String, String>,
+    /// Ports to publish, in the form `host:container` or `container`.
+    pub ports: Vec<String>,
+    /// Volumes to mount, in the form `source:destination`.
+    pub volumes: Vec<String>,
+    /// Extra options for the container runtime's `run` command.
+    pub options: Option<String>,
+}
+
+impl Container {
+    /// Parses a container, which may be written as just its image.
+    /// Returns `None` if no container is configured.
+    pub fn from_yaml(yaml: &Yaml) -> Result<Option<Self>, YamlParseError> {
+        if let Some(image) = yaml.as_str() {
+            return Ok(Some(Self {
+                image: image.to_string(),
+                env: HashMap::new(),
+                ports: vec![],
+                volumes: vec![],
+                options: None,
+            }));
+        }
+        if yaml.as_hash().is_none() {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            image: yaml["image"]
+                .as_str()
+                .ok_or(YamlParseError::MissingField)?
+                .to_string(),
+            env: env_from(&yaml["env"])?,
+            ports: list_from(&yaml["ports"])?,
+            volumes: list_from(&yaml["volumes"])?,
+            options: yaml["options"].as_str().map(str::to_string),
+        }))
+    }
+
+    /// Evaluates any expressions in the container's configuration.
+    pub fn interpolate(&self, context: &expr::Context) -> Result<Self, ExpressionError> {
+        let interpolate = |s: &String| expr::interpolate(s, context);
+
+        Ok(Self {
+            image: interpolate(&self.image)?,
+            env: self
+                .env
+                .iter()
+                .map(|(k, v)| Ok((k.clone(), interpolate(v)?)))
+                .collect::<Result<_, ExpressionError>>()?,
+            ports: self
+                .ports
+                .iter()
+                .map(interpolate)
+                .collect::<Result<_, _>>()?,
+            volumes: self
+                .volumes
+                .iter()
+                .map(interpolate)
+                .collect::<Result<_, _>>()?,
+            options: self.options.as_ref().map(interpolate).transpose()?,
+        })
+    }
+}
+
+/// Parses the `services` of a job, in the order they are declared.
+pub fn services_from(yaml: &Yaml) -> Result<Vec<(String, Container)>, YamlParseError> {
+    let services = match yaml.as_hash() {
+        Some(services) => services,
+        None => return Ok(vec![]),
+    };
+
+    let mut parsed = vec![];
+    for (id, service) in services {
+        let id = id.as_str().ok_or(YamlParseError::MissingField)?.to_string();
+        if let Some(container) = Container::from_yaml(service)? {
+            parsed.push((id, container));
+        }
+    }
+
+    Ok(parsed)
+}
+
+/// Parses a list of scalars, such as ports which may be numbers.
+fn list_from(yaml: &Yaml) -> Result<Vec<String>, YamlParseError> {
+    match yaml.as_vec() {
+        Some(items) => items
+            .iter()
+            .map(|item| scalar_to_string(item).ok_or(YamlParseError::MissingField))
+            .collect(),
+        None => Ok(vec![]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use yaml_rust::YamlLoader;
+
+    #[test]
+    fn parse_containers() {
+        let yaml = YamlLoader::load_from_str(
+            r#"
+container: rust:1.70
+services:
+  postgres:
+    image: postgres:15
+    env:
+      POSTGRES_PASSWORD: postgres
+    ports:
+      - 5432:5432
+      - 6379
+    options: --health-cmd pg_isready
+"#,
+        )
+        .unwrap()
+        .pop()
+        .unwrap();
+
+        let container = Container::from_yaml(&yaml["container"]).unwrap().unwrap();
+        assert_eq!("rust:1.70", container.image);
+        assert!(container.ports.is_empty());
+
+        let services = services_from(&yaml["services"]).unwrap();
+        assert_eq!(1, services.len());
+        let (id, postgres) = &services[0];
+        assert_eq!("postgres", id);
+        assert_eq!("postgres:15", postgres.image);
+        assert_eq!("postgres", postgres.env["POSTGRES_PASSWORD"]);
+        assert_eq!(vec!["5432:5432", "6379"], postgres.ports);
+        assert_eq!(Some("--health-cmd pg_isready"), postgres.options.as_deref());
+
+        assert_eq!(None, Container::from_yaml(&yaml["missing"]).unwrap());
+    }
+}
@@ -0,0 +1,212 @@
+//! Synthesizes the expression contexts GitHub Actions would provide,
+//! based on the local machine and repository.
+
+use std::{env::temp_dir, path::Path, sync::OnceLock};
+
+use super::expr::{object, Value};
+use crate::{ci::Trigger, git};
+
+/// Repository holds the details of the local repository which the
+/// `github` context is built from.
+struct Repository {
+    branch: String,
+    repository: String,
+    actor: String,
+    sha: String,
+}
+
+/// Returns the details of the local repository. They are looked up
+/// once per run, since the context is built for every expression and
+/// each detail takes a git command.
+fn repository() -> &'static Repository {
+    static REPOSITORY: OnceLock<Repository> = OnceLock::new();
+
+    REPOSITORY.get_or_init(|| Repository {
+        branch: git::current_branch(),
+        repository: git::repository().unwrap_or_default(),
+        actor: git::user_name().unwrap_or_default(),
+        sha: git::head_sha().unwrap_or_default(),
+    })
+}
+
+/// Returns the `github` context for a run triggered by the given
+/// event.
+pub fn github(trigger: Option<&Trigger>, workspace: &Path) -> Value {
+    let Repository {
+        branch,
+        repository,
+        actor,
+        sha,
+    } = repository();
+    let branch = branch.clone();
+    let owner = repository.split('/').next().unwrap_or_default().to_string();
+
+    let (event_name, git_ref, ref_name, head_ref, base_ref, event) = match trigger {
+        Some(Trigger::PullRequest {
+            base_branch,
+            action,
+            ..
+        }) => {
+            let base_ref = base_branch.clone().unwrap_or_default();
+            let event = object(vec![
+                ("action", action.as_str().into()),
+                (
+                    "pull_request",
+                    object(vec![
+                        ("base", object(vec![("ref", base_ref.as_str().into())])),
+                        ("head", object(vec![("ref", branch.as_str().into())])),
+                        ("number", Value::Number(1.0)),
+                    ]),
+                ),
+            ]);
+
+            (
+                "pull_request",
+                "refs/pull/1/merge".to_string(),
+                "1/merge".to_string(),
+                branch,
+                base_ref,
+                event,
+            )
+        }
+        Some(Trigger::Event { name, inputs }) => {
+            let inputs = inputs
+                .iter()
+                .map(|(id, value)| (id.as_str(), value.as_str().into()))
+                .collect::<Vec<_>>();
+
+            (
+                name.as_str(),
+                format!("refs/heads/{}", branch),
+                branch,
+                String::new(),
+                String::new(),
+                object(vec![("inputs", object(inputs))]),
+            )
+        }
+        Some(Trigger::Push { branch, .. }) => (
+            "push",
+            format!("refs/heads/{}", branch),
+            branch.clone(),
+            String::new(),
+            String::new(),
+            object(vec![]),
+        ),
+        None => (
+            "push",
+            format!("refs/heads/{}", branch),
+            branch,
+            String::new(),
+            String::new(),
+            object(vec![]),
+        ),
+    };
+
+    object(vec![
+        ("actor", actor.as_str().into()),
+        ("api_url", "https://api.github.com".into()),
+        ("base_ref", base_ref.as_str().into()),
+        ("event", event),
+        ("event_name", event_name.into()),
+        ("head_ref", head_ref.as_str().into()),
+        ("ref", git_ref.as_str().into()),
+        ("ref_name", ref_name.as_str().into()),
+        ("ref_type", "branch".into()),
+        ("repository", repository.as_str().into()),
+        ("repository_owner", owner.as_str().into()),
+        ("run_attempt", "1".into()),
+        ("run_id", "1".into()),
+        ("run_number", "1".into()),
+        ("server_url", "https://github.com".into()),
+        ("sha", sha.as_str().into()),
+        ("workspace", workspace.to_string_lossy().as_ref().into()),
+    ])
+}
+
+/// Returns the `runner` context describing the local machine.
+pub fn runner() -> Value {
+    let os = match std::env::consts::OS {
+        "linux" => "Linux",
+        "macos" => "macOS",
+        "windows" => "Windows",
+        other => other,
+    };
+    let arch = match std::env::consts::ARCH {
+        "x86_64" => "X64",
+        "x86" => "X86",
+        "aarch64" => "ARM64",
+        "arm" => "ARM",
+        other => other,
+    };
+    let temp = temp_dir();
+
+    object(vec![
+        ("arch", arch.into()),
+        ("environment", "self-hosted".into()),
+        ("name", "belay".into()),
+        ("os", os.into()),
+        ("temp", temp.to_string_lossy().as_ref().into()),
+        ("tool_cache", temp.to_string_lossy().as_ref().into()),
+    ])
+}
+
+/// Returns the environment variables GitHub Actions sets for every
+/// step, from the `github` and `runner` contexts.
+pub fn default_env(github: &Value, runner: &Value) -> Vec<(String, String)> {
+    let mut env = vec![
+        ("CI".to_string(), "true".to_string()),
+        ("GITHUB_ACTIONS".to_string(), "true".to_string()),
+    ];
+
+    let github_keys = [
+        "actor",
+        "api_url",
+        "base_ref",
+        "event_name",
+        "head_ref",
+        "ref",
+        "ref_name",
+        "ref_type",
+        "repository",
+        "repository_owner",
+        "run_attempt",
+        "run_id",
+        "run_number",
+        "server_url",
+        "sha",
+        "workspace",
+    ];
+    for key in &github_keys {
+        let value = github.get(&(*key).into()).to_string();
+        env.push((format!("GITHUB_{}", key.to_uppercase()), value));
+    }
+
+    let runner_keys = ["arch", "environment", "name", "os", "temp", "tool_cache"];
+    for key in &runner_keys {
+        let value = runner.get(&(*key).into()).to_string();
+        env.push((format!("RUNNER_{}", key.to_uppercase()), value));
+    }
+
+    env
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_env_from_contexts() {
+        let github = object(vec![("ref_name", "main".into()), ("sha", "abc123".into())]);
+        let runner = object(vec![("os", "Linux".into())]);
+
+        let env = default_env(&github, &runner);
+        let var = |name: &str| env.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str());
+
+        assert_eq!(Some("true"), var("CI"));
+        assert_eq!(Some("true"), var("GITHUB_ACTIONS"));
+        assert_eq!(Some("main"), var("GITHUB_REF_NAME"));
+        assert_eq!(Some("abc123"), var("GITHUB_SHA"));
+        assert_eq!(Some(""), var("GITHUB_HEAD_REF"));
+        assert_eq!(Some("Linux"), var("RUNNER_OS"));
+    }
+}
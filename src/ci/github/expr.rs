@@ -0,0 +1,1119 @@
+//! An implementation of the GitHub Actions expression language, used
+//! by `${{ }}` interpolations and `if` conditions.
+//!
+//! See https://docs.github.com/en/actions/learn-github-actions/expressions
+
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt::{self, Display},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+use yaml_rust::Yaml;
+
+use super::glob;
+use crate::{ci::JobStatus, git};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(BTreeMap<String, Value>),
+}
+
+impl Value {
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::Null => false,
+            Value::Bool(b) => *b,
+            Value::Number(n) => *n != 0.0 && !n.is_nan(),
+            Value::String(s) => !s.is_empty(),
+            Value::Array(_) | Value::Object(_) => true,
+        }
+    }
+
+    /// Looks up a property of an object, ignoring case as GitHub
+    /// Actions does, or an element of an array.
+    pub fn get(&self, key: &Value) -> Value {
+        match (self, key) {
+            (Value::Object(map), key) => {
+                let key = key.to_string();
+                map.get(&key)
+                    .or_else(|| {
+                        map.iter()
+                            .find(|(k, _)| k.eq_ignore_ascii_case(&key))
+                            .map(|(_, v)| v)
+                    })
+                    .cloned()
+                    .unwrap_or(Value::Null)
+            }
+            (Value::Array(items), key) => {
+                let index = key.to_number();
+                if index >= 0.0 && index.fract() == 0.0 {
+                    items.get(index as usize).cloned().unwrap_or(Value::Null)
+                } else {
+                    Value::Null
+                }
+            }
+            _ => Value::Null,
+        }
+    }
+
+    fn to_number(&self) -> f64 {
+        match self {
+            Value::Null => 0.0,
+            Value::Bool(b) => {
+                if *b {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Value::Number(n) => *n,
+            Value::String(s) => parse_number(s.trim()).unwrap_or(f64::NAN),
+            Value::Array(_) | Value::Object(_) => f64::NAN,
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            Value::Null => serde_json::Value::Null,
+            Value::Bool(b) => serde_json::Value::Bool(*b),
+            Value::Number(n) => serde_json::Number::from_f64(*n)
+                .map(serde_json::Value::Number)
+                .unwrap_or(serde_json::Value::Null),
+            Value::String(s) => serde_json::Value::String(s.clone()),
+            Value::Array(items) => {
+                serde_json::Value::Array(items.iter().map(Value::to_json).collect())
+            }
+            Value::Object(map) => serde_json::Value::Object(
+                map.iter().map(|(k, v)| (k.clone(), v.to_json())).collect(),
+            ),
+        }
+    }
+
+    fn from_json(json: &serde_json::Value) -> Self {
+        match json {
+            serde_json::Value::Null => Value::Null,
+            serde_json::Value::Bool(b) => Value::Bool(*b),
+            serde_json::Value::Number(n) => Value::Number(n.as_f64().unwrap_or(f64::NAN)),
+            serde_json::Value::String(s) => Value::String(s.clone()),
+            serde_json::Value::Array(items) => {
+                Value::Array(items.iter().map(Value::from_json).collect())
+            }
+            serde_json::Value::Object(map) => Value::Object(
+                map.iter()
+                    .map(|(k, v)| (k.clone(), Value::from_json(v)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// Converts a value to a string the way GitHub Actions does when
+/// interpolating it.
+impl Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            Value::Null => Ok(()),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) if n.is_infinite() && *n > 0.0 => write!(f, "Infinity"),
+            Value::Number(n) if n.is_infinite() => write!(f, "-Infinity"),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::String(s) => write!(f, "{}", s),
+            Value::Array(_) => write!(f, "Array"),
+            Value::Object(_) => write!(f, "Object"),
+        }
+    }
+}
+
+impl From<&Yaml> for Value {
+    fn from(yaml: &Yaml) -> Self {
+        match yaml {
+            Yaml::Real(s) => Value::Number(s.parse().unwrap_or(f64::NAN)),
+            Yaml::Integer(i) => Value::Number(*i as f64),
+            Yaml::String(s) => Value::String(s.clone()),
+            Yaml::Boolean(b) => Value::Bool(*b),
+            Yaml::Array(items) => Value::Array(items.iter().map(Value::from).collect()),
+            Yaml::Hash(hash) => Value::Object(
+                hash.iter()
+                    .filter_map(|(k, v)| k.as_str().map(|k| (k.to_string(), Value::from(v))))
+                    .collect(),
+            ),
+            Yaml::Alias(_) | Yaml::Null | Yaml::BadValue => Value::Null,
+        }
+    }
+}
+
+/// Creates an object from its entries.
+pub fn object<'a>(entries: impl IntoIterator<Item = (&'a str, Value)>) -> Value {
+    Value::Object(
+        entries
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect(),
+    )
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.to_string())
+    }
+}
+
+/// Everything an expression can refer to.
+#[derive(Clone)]
+pub struct Context {
+    /// The named contexts, such as `github`, `env` and `matrix`.
+    pub values: BTreeMap<String, Value>,
+    pub status: JobStatus,
+    /// The directory `hashFiles` patterns are relative to.
+    pub workspace: PathBuf,
+}
+
+impl Context {
+    pub fn new() -> Self {
+        Self {
+            values: BTreeMap::new(),
+            status: JobStatus::Success,
+            workspace: PathBuf::from("."),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ExpressionError {
+    expression: String,
+    message: String,
+}
+
+impl ExpressionError {
+    pub fn new(expression: &str, message: impl Into<String>) -> Self {
+        Self {
+            expression: expression.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for ExpressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(
+            f,
+            "Invalid expression '{}': {}",
+            self.expression, self.message
+        )
+    }
+}
+
+impl Error for ExpressionError {}
+
+/// Evaluates a single expression, such as `matrix.rust == 'stable'`.
+pub fn evaluate(expression: &str, context: &Context) -> Result<Value, ExpressionError> {
+    let tokens = lex(expression).map_err(|e| ExpressionError::new(expression, e))?;
+    let expr = Parser::new(&tokens)
+        .parse()
+        .map_err(|e| ExpressionError::new(expression, e))?;
+
+    Evaluator { context }
+        .evaluate(&expr)
+        .map(|(value, _)| value)
+        .map_err(|e| ExpressionError::new(expression, e))
+}
+
+/// Evaluates an `if` condition. As in GitHub Actions, the condition
+/// may be wrapped in `${{ }}`, and unless it calls one of the status
+/// check functions it is implicitly combined with `success()`.
+pub fn evaluate_condition(condition: &str, context: &Context) -> Result<bool, ExpressionError> {
+    let condition = condition.trim();
+    let expression = match condition.strip_prefix("${{") {
+        Some(inner) => {
+            let end = expression_end(inner)
+                .ok_or_else(|| ExpressionError::new(condition, "missing closing '}}'"))?;
+            if inner[end..] == *"}}" {
+                &inner[..end]
+            } else {
+                condition
+            }
+        }
+        None => condition,
+    };
+
+    let tokens = lex(expression).map_err(|e| ExpressionError::new(expression, e))?;
+    let expr = Parser::new(&tokens)
+        .parse()
+        .map_err(|e| ExpressionError::new(expression, e))?;
+
+    let evaluator = Evaluator { context };
+    let value = evaluator
+        .value(&expr)
+        .map_err(|e| ExpressionError::new(expression, e))?;
+
+    if expr.calls_status_function() {
+        Ok(value.is_truthy())
+    } else {
+        Ok(context.status == JobStatus::Success && value.is_truthy())
+    }
+}
+
+/// Replaces each `${{ <expression> }}` in the input with the result
+/// of evaluating the expression.
+pub fn interpolate(input: &str, context: &Context) -> Result<String, ExpressionError> {
+    let mut output = String::new();
+    let mut rest = input;
+
+    while let Some(start) = rest.find("${{") {
+        output.push_str(&rest[..start]);
+
+        let expression_start = start + 3;
+        let expression_len = expression_end(&rest[expression_start..])
+            .ok_or_else(|| ExpressionError::new(&rest[start..], "missing closing '}}'"))?;
+        let expression = &rest[expression_start..expression_start + expression_len];
+
+        output.push_str(&evaluate(expression, context)?.to_string());
+
+        rest = &rest[expression_start + expression_len + 2..];
+    }
+    output.push_str(rest);
+
+    Ok(output)
+}
+
+/// Finds the `}}` closing an expression, ignoring any inside string
+/// literals.
+fn expression_end(input: &str) -> Option<usize> {
+    let mut in_string = false;
+
+    for (i, c) in input.char_indices() {
+        match c {
+            '\'' => in_string = !in_string,
+            '}' if !in_string && input[i..].starts_with("}}") => return Some(i),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+fn parse_number(input: &str) -> Option<f64> {
+    if input.is_empty() {
+        return Some(0.0);
+    }
+
+    let (negative, digits) = match input.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, input.strip_prefix('+').unwrap_or(input)),
+    };
+    let number = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()? as f64
+    } else if let Some(octal) = digits.strip_prefix("0o") {
+        i64::from_str_radix(octal, 8).ok()? as f64
+    } else if digits == "Infinity" {
+        f64::INFINITY
+    } else if digits
+        .chars()
+        .all(|c| c.is_ascii_digit() || "eE.+-".contains(c))
+    {
+        digits.parse::<f64>().ok()?
+    } else {
+        return None;
+    };
+
+    Some(if negative { -number } else { number })
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    String(String),
+    Ident(String),
+    LeftParen,
+    RightParen,
+    LeftBracket,
+    RightBracket,
+    Dot,
+    Comma,
+    Star,
+    Not,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+    And,
+    Or,
+}
+
+fn lex(input: &str) -> Result<Vec<Token>, String> {
+    let chars = input.chars().collect::<Vec<char>>();
+    let mut tokens = vec![];
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+
+        let (token, len) = match (c, next) {
+            (c, _) if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            ('(', _) => (Token::LeftParen, 1),
+            (')', _) => (Token::RightParen, 1),
+            ('[', _) => (Token::LeftBracket, 1),
+            (']', _) => (Token::RightBracket, 1),
+            ('.', _) => (Token::Dot, 1),
+            (',', _) => (Token::Comma, 1),
+            ('*', _) => (Token::Star, 1),
+            ('!', Some('=')) => (Token::NotEqual, 2),
+            ('!', _) => (Token::Not, 1),
+            ('<', Some('=')) => (Token::LessEqual, 2),
+            ('<', _) => (Token::Less, 1),
+            ('>', Some('=')) => (Token::GreaterEqual, 2),
+            ('>', _) => (Token::Greater, 1),
+            ('=', Some('=')) => (Token::Equal, 2),
+            ('&', Some('&')) => (Token::And, 2),
+            ('|', Some('|')) => (Token::Or, 2),
+            ('\'', _) => {
+                let mut value = String::new();
+                let mut j = i + 1;
+                loop {
+                    match (chars.get(j), chars.get(j + 1)) {
+                        (Some('\''), Some('\'')) => {
+                            value.push('\'');
+                            j += 2;
+                        }
+                        (Some('\''), _) => break,
+                        (Some(c), _) => {
+                            value.push(*c);
+                            j += 1;
+                        }
+                        (None, _) => return Err("unterminated string".into()),
+                    }
+                }
+                (Token::String(value), j + 1 - i)
+            }
+            (c, next)
+                if c.is_ascii_digit()
+                    || (c == '-' && next.map(|n| n.is_ascii_digit()).unwrap_or(false)) =>
+            {
+                let len = chars[i + 1..]
+                    .iter()
+                    .zip(chars[i..].iter())
+                    .take_while(|(&c, &prev)| {
+                        c.is_ascii_alphanumeric()
+                            || c == '.'
+                            || ((c == '-' || c == '+') && (prev == 'e' || prev == 'E'))
+                    })
+                    .count()
+                    + 1;
+                let literal = chars[i..i + len].iter().collect::<String>();
+                let number =
+                    parse_number(&literal).ok_or(format!("invalid number '{}'", literal))?;
+                (Token::Number(number), len)
+            }
+            (c, _) if c.is_ascii_alphabetic() || c == '_' => {
+                let len = chars[i..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_alphanumeric() || **c == '_' || **c == '-')
+                    .count();
+                (Token::Ident(chars[i..i + len].iter().collect()), len)
+            }
+            (c, _) => return Err(format!("unexpected character '{}'", c)),
+        };
+
+        tokens.push(token);
+        i += len;
+    }
+
+    Ok(tokens)
+}
+
+#[derive(Debug, PartialEq)]
+enum Expr {
+    Literal(Value),
+    Context(String),
+    Index(Box<Expr>, Box<Expr>),
+    /// The `*` object filter, as in `github.event.commits.*.message`.
+    Filter(Box<Expr>),
+    Call(String, Vec<Expr>),
+    Not(Box<Expr>),
+    Binary(BinaryOperator, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn calls_status_function(&self) -> bool {
+        match self {
+            Expr::Literal(_) | Expr::Context(_) => false,
+            Expr::Index(target, key) => {
+                target.calls_status_function() || key.calls_status_function()
+            }
+            Expr::Filter(target) | Expr::Not(target) => target.calls_status_function(),
+            Expr::Call(name, args) => {
+                ["success", "failure", "cancelled", "always"]
+                    .iter()
+                    .any(|f| name.eq_ignore_ascii_case(f))
+                    || args.iter().any(Expr::calls_status_function)
+            }
+            Expr::Binary(_, left, right) => {
+                left.calls_status_function() || right.calls_status_function()
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BinaryOperator {
+    And,
+    Or,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+/// A recursive descent parser, with one function per level of
+/// operator precedence.
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(tokens: &'a [Token]) -> Self {
+        Self {
+            tokens,
+            position: 0,
+        }
+    }
+
+    fn parse(mut self) -> Result<Expr, String> {
+        let expr = self.or()?;
+
+        match self.peek() {
+            None => Ok(expr),
+            Some(token) => Err(format!("unexpected token {:?}", token)),
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next() {
+            Some(ref token) if *token == expected => Ok(()),
+            Some(token) => Err(format!("expected {:?}, found {:?}", expected, token)),
+            None => Err(format!("expected {:?}", expected)),
+        }
+    }
+
+    fn binary(
+        &mut self,
+        operators: &[(Token, BinaryOperator)],
+        operand: fn(&mut Self) -> Result<Expr, String>,
+    ) -> Result<Expr, String> {
+        let mut left = operand(self)?;
+
+        while let Some(&(_, operator)) = operators
+            .iter()
+            .find(|(token, _)| Some(token) == self.peek())
+        {
+            self.next();
+            let right = operand(self)?;
+            left = Expr::Binary(operator, Box::new(left), Box::new(right));
+        }
+
+        Ok(left)
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        self.binary(&[(Token::Or, BinaryOperator::Or)], Self::and)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        self.binary(&[(Token::And, BinaryOperator::And)], Self::equality)
+    }
+
+    fn equality(&mut self) -> Result<Expr, String> {
+        self.binary(
+            &[
+                (Token::Equal, BinaryOperator::Equal),
+                (Token::NotEqual, BinaryOperator::NotEqual),
+            ],
+            Self::comparison,
+        )
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        self.binary(
+            &[
+                (Token::Less, BinaryOperator::Less),
+                (Token::LessEqual, BinaryOperator::LessEqual),
+                (Token::Greater, BinaryOperator::Greater),
+                (Token::GreaterEqual, BinaryOperator::GreaterEqual),
+            ],
+            Self::unary,
+        )
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.peek() == Some(&Token::Not) {
+            self.next();
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+
+        self.postfix()
+    }
+
+    fn postfix(&mut self) -> Result<Expr, String> {
+        let mut expr = self.primary()?;
+
+        loop {
+            match self.peek() {
+                Some(Token::Dot) => {
+                    self.next();
+                    expr = match self.next() {
+                        Some(Token::Star) => Expr::Filter(Box::new(expr)),
+                        Some(Token::Ident(name)) => Expr::Index(
+                            Box::new(expr),
+                            Box::new(Expr::Literal(Value::String(name))),
+                        ),
+                        Some(Token::Number(n)) => {
+                            Expr::Index(Box::new(expr), Box::new(Expr::Literal(Value::Number(n))))
+                        }
+                        token => return Err(format!("expected property name, found {:?}", token)),
+                    };
+                }
+                Some(Token::LeftBracket) => {
+                    self.next();
+                    if self.peek() == Some(&Token::Star) {
+                        self.next();
+                        expr = Expr::Filter(Box::new(expr));
+                    } else {
+                        let index = self.or()?;
+                        expr = Expr::Index(Box::new(expr), Box::new(index));
+                    }
+                    self.expect(Token::RightBracket)?;
+                }
+                _ => return Ok(expr),
+            }
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Expr::Literal(Value::Number(n))),
+            Some(Token::String(s)) => Ok(Expr::Literal(Value::String(s))),
+            Some(Token::LeftParen) => {
+                let expr = self.or()?;
+                self.expect(Token::RightParen)?;
+                Ok(expr)
+            }
+            Some(Token::Ident(name)) => match name.as_str() {
+                "true" => Ok(Expr::Literal(Value::Bool(true))),
+                "false" => Ok(Expr::Literal(Value::Bool(false))),
+                "null" => Ok(Expr::Literal(Value::Null)),
+                "NaN" => Ok(Expr::Literal(Value::Number(f64::NAN))),
+                "Infinity" => Ok(Expr::Literal(Value::Number(f64::INFINITY))),
+                _ if self.peek() == Some(&Token::LeftParen) => {
+                    self.next();
+                    let mut args = vec![];
+                    if self.peek() != Some(&Token::RightParen) {
+                        loop {
+                            args.push(self.or()?);
+                            if self.peek() != Some(&Token::Comma) {
+                                break;
+                            }
+                            self.next();
+                        }
+                    }
+                    self.expect(Token::RightParen)?;
+                    Ok(Expr::Call(name, args))
+                }
+                _ => Ok(Expr::Context(name)),
+            },
+            Some(token) => Err(format!("unexpected token {:?}", token)),
+            None => Err("unexpected end of expression".into()),
+        }
+    }
+}
+
+struct Evaluator<'a> {
+    context: &'a Context,
+}
+
+impl<'a> Evaluator<'a> {
+    /// Evaluates an expression, also returning whether the result is
+    /// the output of an object filter. Indexing into a filtered array
+    /// indexes into each of its elements.
+    fn evaluate(&self, expr: &Expr) -> Result<(Value, bool), String> {
+        let value = match expr {
+            Expr::Literal(value) => value.clone(),
+            Expr::Context(name) => self
+                .context
+                .values
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(name))
+                .map(|(_, v)| v.clone())
+                .unwrap_or(Value::Null),
+            Expr::Index(target, key) => {
+                let (target, filtered) = self.evaluate(target)?;
+                let key = self.value(key)?;
+
+                return Ok(match (target, filtered) {
+                    (Value::Array(items), true) => (
+                        Value::Array(
+                            items
+                                .iter()
+                                .map(|item| item.get(&key))
+                                .filter(|item| *item != Value::Null)
+                                .collect(),
+                        ),
+                        true,
+                    ),
+                    (target, _) => (target.get(&key), false),
+                });
+            }
+            Expr::Filter(target) => {
+                let (target, filtered) = self.evaluate(target)?;
+
+                fn children(value: Value) -> Vec<Value> {
+                    match value {
+                        Value::Array(items) => items,
+                        Value::Object(map) => map.into_values().collect(),
+                        _ => vec![],
+                    }
+                }
+
+                let items = match (target, filtered) {
+                    (Value::Array(items), true) => items.into_iter().flat_map(children).collect(),
+                    (target, _) => children(target),
+                };
+
+                return Ok((Value::Array(items), true));
+            }
+            Expr::Call(name, args) => self.call(name, args)?,
+            Expr::Not(expr) => Value::Bool(!self.value(expr)?.is_truthy()),
+            Expr::Binary(BinaryOperator::And, left, right) => {
+                // Logical operators return one of their operands, not a boolean
+                let left = self.value(left)?;
+                if left.is_truthy() {
+                    self.value(right)?
+                } else {
+                    left
+                }
+            }
+            Expr::Binary(BinaryOperator::Or, left, right) => {
+                let left = self.value(left)?;
+                if left.is_truthy() {
+                    left
+                } else {
+                    self.value(right)?
+                }
+            }
+            Expr::Binary(operator, left, right) => {
+                let left = self.value(left)?;
+                let right = self.value(right)?;
+
+                Value::Bool(match operator {
+                    BinaryOperator::Equal => loose_equal(&left, &right),
+                    BinaryOperator::NotEqual => !loose_equal(&left, &right),
+                    BinaryOperator::Less => compare(&left, &right, |o| o.is_lt()),
+                    BinaryOperator::LessEqual => compare(&left, &right, |o| o.is_le()),
+                    BinaryOperator::Greater => compare(&left, &right, |o| o.is_gt()),
+                    BinaryOperator::GreaterEqual => compare(&left, &right, |o| o.is_ge()),
+                    BinaryOperator::And | BinaryOperator::Or => unreachable!(),
+                })
+            }
+        };
+
+        Ok((value, false))
+    }
+
+    fn value(&self, expr: &Expr) -> Result<Value, String> {
+        self.evaluate(expr).map(|(value, _)| value)
+    }
+
+    fn call(&self, name: &str, args: &[Expr]) -> Result<Value, String> {
+        let args = args
+            .iter()
+            .map(|arg| self.value(arg))
+            .collect::<Result<Vec<Value>, String>>()?;
+        let arity = |min: usize, max: usize| {
+            if args.len() < min || args.len() > max {
+                Err(format!("wrong number of arguments for function '{}'", name))
+            } else {
+                Ok(())
+            }
+        };
+        let lowercase = |value: &Value| value.to_string().to_lowercase();
+
+        Ok(match name.to_lowercase().as_str() {
+            "contains" => {
+                arity(2, 2)?;
+                match &args[0] {
+                    Value::Array(items) => {
+                        Value::Bool(items.iter().any(|item| loose_equal(item, &args[1])))
+                    }
+                    search => Value::Bool(lowercase(search).contains(&lowercase(&args[1]))),
+                }
+            }
+            "startswith" => {
+                arity(2, 2)?;
+                Value::Bool(lowercase(&args[0]).starts_with(&lowercase(&args[1])))
+            }
+            "endswith" => {
+                arity(2, 2)?;
+                Value::Bool(lowercase(&args[0]).ends_with(&lowercase(&args[1])))
+            }
+            "format" => {
+                arity(1, usize::MAX)?;
+                Value::String(format(&args[0].to_string(), &args[1..])?)
+            }
+            "join" => {
+                arity(1, 2)?;
+                let separator = args
+                    .get(1)
+                    .map(Value::to_string)
+                    .unwrap_or_else(|| ",".into());
+                match &args[0] {
+                    Value::Array(items) => Value::String(
+                        items
+                            .iter()
+                            .map(Value::to_string)
+                            .collect::<Vec<String>>()
+                            .join(&separator),
+                    ),
+                    value => Value::String(value.to_string()),
+                }
+            }
+            "tojson" => {
+                arity(1, 1)?;
+                Value::String(
+                    serde_json::to_string_pretty(&args[0].to_json()).map_err(|e| e.to_string())?,
+                )
+            }
+            "fromjson" => {
+                arity(1, 1)?;
+                let json = serde_json::from_str::<serde_json::Value>(&args[0].to_string())
+                    .map_err(|e| e.to_string())?;
+                Value::from_json(&json)
+            }
+            "hashfiles" => {
+                arity(1, usize::MAX)?;
+                let patterns = args.iter().map(Value::to_string).collect::<Vec<String>>();
+                Value::String(hash_files(&self.context.workspace, &patterns)?)
+            }
+            "success" => {
+                arity(0, 0)?;
+                Value::Bool(self.context.status == JobStatus::Success)
+            }
+            "failure" => {
+                arity(0, 0)?;
+                Value::Bool(self.context.status == JobStatus::Failure)
+            }
+            "cancelled" => {
+                arity(0, 0)?;
+                Value::Bool(self.context.status == JobStatus::Cancelled)
+            }
+            "always" => {
+                arity(0, 0)?;
+                Value::Bool(true)
+            }
+            _ => return Err(format!("unrecognized function '{}'", name)),
+        })
+    }
+}
+
+/// Compares values for equality, coercing them to numbers if they
+/// are of different types. Strings are compared ignoring case.
+fn loose_equal(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Null, Value::Null) => true,
+        (Value::Bool(l), Value::Bool(r)) => l == r,
+        (Value::Number(l), Value::Number(r)) => l == r,
+        (Value::String(l), Value::String(r)) => l.to_lowercase() == r.to_lowercase(),
+        (Value::Array(_), Value::Array(_)) | (Value::Object(_), Value::Object(_)) => left == right,
+        (Value::Array(_), _)
+        | (Value::Object(_), _)
+        | (_, Value::Array(_))
+        | (_, Value::Object(_)) => false,
+        _ => left.to_number() == right.to_number(),
+    }
+}
+
+fn compare(left: &Value, right: &Value, check: fn(std::cmp::Ordering) -> bool) -> bool {
+    let ordering = match (left, right) {
+        (Value::String(l), Value::String(r)) => Some(l.to_lowercase().cmp(&r.to_lowercase())),
+        _ => left.to_number().partial_cmp(&right.to_number()),
+    };
+
+    ordering.map(check).unwrap_or(false)
+}
+
+/// Implements the `format` function, which replaces `{N}` with the
+/// Nth argument and unescapes `{{` and `}}`.
+fn format(input: &str, args: &[Value]) -> Result<String, String> {
+    let mut output = String::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('{', Some('{')) | ('}', Some('}')) => {
+                chars.next();
+                output.push(c);
+            }
+            ('{', _) => {
+                let mut index = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) if c.is_ascii_digit() => index.push(c),
+                        _ => return Err(format!("invalid format string '{}'", input)),
+                    }
+                }
+                let arg = index
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|index| args.get(index))
+                    .ok_or(format!("invalid format string '{}'", input))?;
+                output.push_str(&arg.to_string());
+            }
+            ('}', _) => return Err(format!("invalid format string '{}'", input)),
+            (c, _) => output.push(c),
+        }
+    }
+
+    Ok(output)
+}
+
+/// Implements the `hashFiles` function, which returns a SHA-256 hash
+/// of the files in the workspace matching the patterns, or an empty
+/// string if there are none. Patterns starting with `!` exclude files.
+fn hash_files(workspace: &Path, patterns: &[String]) -> Result<String, String> {
+    fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                if !path.ends_with(".git") {
+                    walk(&path, files)?;
+                }
+            } else {
+                files.push(path);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Ignored files, such as those in `target`, are skipped, since
+    // walking them is slow and they aren't in the checkout on GitHub
+    let mut files = match git::files(workspace) {
+        Some(files) => files
+            .into_iter()
+            .map(|file| workspace.join(file))
+            .filter(|file| file.is_file())
+            .collect(),
+        None => {
+            let mut files = vec![];
+            walk(workspace, &mut files).map_err(|e| e.to_string())?;
+            files
+        }
+    };
+    files.sort();
+
+    let mut hasher = Sha256::new();
+    let mut matched = false;
+    for file in files {
+        let relative = file
+            .strip_prefix(workspace)
+            .map_err(|e| e.to_string())?
+            .to_string_lossy()
+            .replace('\\', "/");
+
+        let mut included = false;
+        for pattern in patterns {
+            match pattern.strip_prefix('!') {
+                Some(pattern) => included &= !glob::is_match(pattern, &relative),
+                None => included |= glob::is_match(pattern, &relative),
+            }
+        }
+
+        if included {
+            let contents = fs::read(&file).map_err(|e| e.to_string())?;
+            hasher.input(Sha256::digest(&contents));
+            matched = true;
+        }
+    }
+
+    if matched {
+        Ok(hasher
+            .result()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect())
+    } else {
+        Ok(String::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> Context {
+        let mut context = Context::new();
+
+        let mut github = BTreeMap::new();
+        github.insert("event_name".to_string(), "push".into());
+        github.insert("ref".to_string(), "refs/heads/main".into());
+        context
+            .values
+            .insert("github".into(), Value::Object(github));
+
+        let mut matrix = BTreeMap::new();
+        matrix.insert("rust".to_string(), "stable".into());
+        matrix.insert(
+            "features".to_string(),
+            Value::Array(vec!["a".into(), "b".into()]),
+        );
+        context
+            .values
+            .insert("matrix".into(), Value::Object(matrix));
+
+        context
+    }
+
+    fn eval(expression: &str) -> Value {
+        evaluate(expression, &context()).unwrap()
+    }
+
+    #[test]
+    fn evaluate_literals() {
+        assert_eq!(Value::Null, eval("null"));
+        assert_eq!(Value::Bool(true), eval("true"));
+        assert_eq!(Value::Number(-2.5), eval("-2.5"));
+        assert_eq!(Value::Number(255.0), eval("0xff"));
+        assert_eq!(Value::String("it's".into()), eval("'it''s'"));
+    }
+
+    #[test]
+    fn evaluate_contexts() {
+        assert_eq!(Value::String("push".into()), eval("github.event_name"));
+        assert_eq!(Value::String("push".into()), eval("GitHub['EVENT_NAME']"));
+        assert_eq!(Value::String("b".into()), eval("matrix.features[1]"));
+        assert_eq!(Value::Null, eval("matrix.missing.nested"));
+    }
+
+    #[test]
+    fn evaluate_operators() {
+        assert_eq!(Value::Bool(true), eval("github.event_name == 'PUSH'"));
+        assert_eq!(Value::Bool(true), eval("'1' == 1 && true == 1"));
+        assert_eq!(Value::Bool(false), eval("!(1 < 2)"));
+        assert_eq!(Value::String("stable".into()), eval("false || matrix.rust"));
+        assert_eq!(Value::String("".into()), eval("'' && matrix.rust"));
+    }
+
+    #[test]
+    fn evaluate_functions() {
+        assert_eq!(Value::Bool(true), eval("contains(matrix.features, 'A')"));
+        assert_eq!(
+            Value::Bool(true),
+            eval("startsWith(github.ref, 'refs/heads/')")
+        );
+        assert_eq!(
+            Value::String("{stable} a-b".into()),
+            eval("format('{{{0}}} {1}', matrix.rust, join(matrix.features, '-'))")
+        );
+        assert_eq!(
+            Value::String("[\n  \"a\",\n  \"b\"\n]".into()),
+            eval("toJSON(matrix.features)")
+        );
+        assert_eq!(Value::Number(2.0), eval("fromJSON('{\"a\": [1, 2]}').a[1]"));
+        assert_eq!(Value::Bool(true), eval("success() && !failure()"));
+    }
+
+    #[test]
+    fn evaluate_object_filter() {
+        assert_eq!(
+            Value::Array(vec!["1".into(), "2".into()]),
+            eval("fromJSON('[{\"id\": \"1\"}, {\"id\": \"2\"}, {}]').*.id")
+        );
+    }
+
+    #[test]
+    fn evaluate_conditions() {
+        let mut context = context();
+
+        assert!(evaluate_condition("github.event_name == 'push'", &context).unwrap());
+        assert!(evaluate_condition("${{ matrix.rust == 'stable' }}", &context).unwrap());
+        assert!(!evaluate_condition("failure()", &context).unwrap());
+
+        context.status = JobStatus::Failure;
+        assert!(!evaluate_condition("github.event_name == 'push'", &context).unwrap());
+        assert!(evaluate_condition("always()", &context).unwrap());
+        assert!(evaluate_condition("failure() && matrix.rust == 'stable'", &context).unwrap());
+    }
+
+    #[test]
+    fn evaluate_unterminated_conditions() {
+        let context = context();
+
+        assert!(evaluate_condition("${{", &context).is_err());
+        assert!(evaluate_condition("${{ matrix.rust == 'stable'", &context).is_err());
+        assert!(evaluate_condition("${{ '}}' }}", &context).unwrap());
+    }
+
+    #[test]
+    fn hash_ignores_ignored_files() {
+        let workspace = assert_fs::TempDir::new().unwrap();
+        std::process::Command::new("git")
+            .arg("init")
+            .current_dir(workspace.path())
+            .output()
+            .unwrap();
+        fs::write(workspace.path().join(".gitignore"), "target/\n").unwrap();
+        fs::write(workspace.path().join("Cargo.lock"), "lock").unwrap();
+        let hash = hash_files(workspace.path(), &["**/Cargo.lock".into()]).unwrap();
+
+        fs::create_dir(workspace.path().join("target")).unwrap();
+        fs::write(workspace.path().join("target/Cargo.lock"), "build").unwrap();
+        assert_eq!(
+            hash,
+            hash_files(workspace.path(), &["**/Cargo.lock".into()]).unwrap()
+        );
+
+        fs::create_dir(workspace.path().join("src")).unwrap();
+        fs::write(workspace.path().join("src/Cargo.lock"), "untracked").unwrap();
+        assert_ne!(
+            hash,
+            hash_files(workspace.path(), &["**/Cargo.lock".into()]).unwrap()
+        );
+    }
+
+    #[test]
+    fn interpolate_expressions() {
+        assert_eq!(
+            "cargo +stable test # }}",
+            interpolate("cargo +${{ matrix.rust }} test # ${{ '}}' }}", &context()).unwrap()
+        );
+        assert!(interpolate("${{ matrix.rust", &context()).is_err());
+        assert!(interpolate("${{ unknown() }}", &context()).is_err());
+    }
+}
@@ -0,0 +1,163 @@
+//! The branch, tag and path filters of the `on` block of a workflow.
+
+use yaml_rust::Yaml;
+
+use super::{glob, YamlParseError};
+
+/// Filter restricts which names, such as branch names, trigger a
+/// workflow. It is parsed from a pair of fields such as `branches`
+/// and `branches-ignore`, only one of which may be used.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Filter {
+    /// Patterns which select names. A pattern starting with `!`
+    /// excludes the names it matches instead, and the last pattern
+    /// which matches a name decides whether it is selected.
+    include: Option<Vec<String>>,
+    /// Patterns which exclude names, from the `-ignore` field.
+    ignore: Option<Vec<String>>,
+}
+
+impl Filter {
+    /// Parses the filter named `key`, along with its `-ignore` form,
+    /// from the configuration of an event.
+    pub fn from_yaml(event: &Yaml, key: &str) -> Result<Self, YamlParseError> {
+        let ignore_key = format!("{}-ignore", key);
+        let include = patterns_from(&event[key], key)?;
+        let ignore = patterns_from(&event[ignore_key.as_str()], &ignore_key)?;
+
+        if include.is_some() && ignore.is_some() {
+            return Err(YamlParseError::InvalidFilter(format!(
+                "'{}' and '{}' can't be used together",
+                key, ignore_key
+            )));
+        }
+        if let Some(pattern) = ignore.iter().flatten().find(|p| p.starts_with('!')) {
+            return Err(YamlParseError::InvalidFilter(format!(
+                "'{}' can't contain the negated pattern '{}'",
+                ignore_key, pattern
+            )));
+        }
+
+        Ok(Self { include, ignore })
+    }
+
+    /// Returns whether the filter was configured at all.
+    pub fn is_set(&self) -> bool {
+        self.include.is_some() || self.ignore.is_some()
+    }
+
+    /// Returns whether the name passes the filter. An unset filter
+    /// allows every name.
+    pub fn allows(&self, name: &str) -> bool {
+        if let Some(include) = &self.include {
+            let mut selected = false;
+            for pattern in include {
+                match pattern.strip_prefix('!') {
+                    Some(negated) if glob::is_filter_match(negated, name) => selected = false,
+                    Some(_) => {}
+                    None if glob::is_filter_match(pattern, name) => selected = true,
+                    None => {}
+                }
+            }
+
+            return selected;
+        }
+
+        match &self.ignore {
+            Some(ignore) => !ignore
+                .iter()
+                .any(|pattern| glob::is_filter_match(pattern, name)),
+            None => true,
+        }
+    }
+
+    /// Returns whether any of the names pass the filter, as is needed
+    /// for path filters to run a workflow. If the names aren't known
+    /// the filter is ignored.
+    pub fn allows_any(&self, names: Option<&[String]>) -> bool {
+        match names {
+            Some(names) if self.is_set() => names.iter().any(|name| self.allows(name)),
+            _ => true,
+        }
+    }
+}
+
+/// Parses a list of patterns, which may also be written as a single
+/// pattern.
+fn patterns_from(yaml: &Yaml, key: &str) -> Result<Option<Vec<String>>, YamlParseError> {
+    let invalid = || YamlParseError::InvalidFilter(format!("'{}' must be a list of patterns", key));
+
+    match yaml {
+        Yaml::BadValue => Ok(None),
+        Yaml::String(pattern) => Ok(Some(vec![pattern.clone()])),
+        Yaml::Array(patterns) => patterns
+            .iter()
+            .map(|pattern| pattern.as_str().map(str::to_string).ok_or_else(invalid))
+            .collect::<Result<_, _>>()
+            .map(Some),
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use yaml_rust::YamlLoader;
+
+    fn filter(yaml: &str) -> Result<Filter, YamlParseError> {
+        let yaml = YamlLoader::load_from_str(yaml)?.pop().unwrap();
+
+        Filter::from_yaml(&yaml, "branches")
+    }
+
+    #[test]
+    fn filter_branches() -> Result<(), YamlParseError> {
+        let filter = filter("branches: [main, 'releases/**', '!releases/**-alpha']")?;
+
+        assert!(filter.is_set());
+        assert!(filter.allows("main"));
+        assert!(filter.allows("releases/10"));
+        assert!(!filter.allows("releases/10-alpha"));
+        assert!(!filter.allows("feature"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn filter_branches_ignore() -> Result<(), YamlParseError> {
+        let filter = filter("branches-ignore: ['mona/octocat', 'releases/**-alpha']")?;
+
+        assert!(filter.allows("main"));
+        assert!(!filter.allows("mona/octocat"));
+        assert!(!filter.allows("releases/beta/3-alpha"));
+
+        assert!(!Filter::default().is_set());
+        assert!(Filter::default().allows("main"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn filter_paths() -> Result<(), YamlParseError> {
+        let yaml = YamlLoader::load_from_str("paths-ignore: ['**.md']")?
+            .pop()
+            .unwrap();
+        let filter = Filter::from_yaml(&yaml, "paths")?;
+
+        let readme = vec!["README.md".to_string()];
+        let source = vec!["README.md".to_string(), "src/main.rs".to_string()];
+        assert!(!filter.allows_any(Some(&readme)));
+        assert!(filter.allows_any(Some(&source)));
+        assert!(filter.allows_any(None));
+        assert!(!filter.allows_any(Some(&[])));
+
+        Ok(())
+    }
+
+    #[test]
+    fn filter_invalid() {
+        assert!(filter("{branches: [main], branches-ignore: [dev]}").is_err());
+        assert!(filter("branches-ignore: ['!main']").is_err());
+    }
+}
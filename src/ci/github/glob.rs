@@ -0,0 +1,196 @@
+//! Matching of the two pattern dialects GitHub uses. Paths passed to
+//! `hashFiles` use ordinary globs, while the branch, tag and path
+//! filters of a workflow use GitHub's filter pattern syntax.
+
+/// Returns true if the path matches the glob pattern.
+///
+/// `*` matches any characters except `/`, `**` matches any characters
+/// including `/`, `?` matches a single character except `/`, and
+/// `[...]` matches one character from a set such as `[abc]`, `[a-z]`
+/// or the negated `[!a-z]`. Special characters can be escaped with `\`.
+pub fn is_match(pattern: &str, path: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<char>>();
+    let path = path.chars().collect::<Vec<char>>();
+
+    matches(&pattern, &path, Syntax::Glob)
+}
+
+/// Returns true if the name matches the filter pattern.
+///
+/// This is the same as `is_match`, except that `?` matches zero or
+/// one of the preceding character and `+` matches one or more of the
+/// preceding character, as in a regular expression.
+pub fn is_filter_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<char>>();
+    let name = name.chars().collect::<Vec<char>>();
+
+    matches(&pattern, &name, Syntax::Filter)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Syntax {
+    Glob,
+    Filter,
+}
+
+/// Atom is a pattern element which matches a single character.
+enum Atom<'a> {
+    Literal(char),
+    Class(&'a [char]),
+}
+
+impl Atom<'_> {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Atom::Literal(literal) => *literal == c,
+            Atom::Class(class) => c != '/' && class_contains(class, c),
+        }
+    }
+}
+
+fn matches(pattern: &[char], path: &[char], syntax: Syntax) -> bool {
+    match pattern {
+        [] => path.is_empty(),
+        ['*', '*', rest @ ..] => {
+            // `**/` may also match zero directories, so `**/*.rs` matches `main.rs`
+            if let ['/', after_slash @ ..] = rest {
+                if matches(after_slash, path, syntax) {
+                    return true;
+                }
+            }
+
+            (0..=path.len()).any(|i| matches(rest, &path[i..], syntax))
+        }
+        ['*', rest @ ..] => {
+            let segment_len = path.iter().take_while(|&&c| c != '/').count();
+
+            (0..=segment_len).any(|i| matches(rest, &path[i..], syntax))
+        }
+        ['?', rest @ ..] if syntax == Syntax::Glob => match path {
+            [c, path_rest @ ..] if *c != '/' => matches(rest, path_rest, syntax),
+            _ => false,
+        },
+        _ => {
+            let (atom, rest) = atom(pattern);
+
+            match (syntax, rest) {
+                (Syntax::Filter, ['?', rest @ ..]) => {
+                    matches(rest, path, syntax)
+                        || match path {
+                            [c, path_rest @ ..] => {
+                                atom.matches(*c) && matches(rest, path_rest, syntax)
+                            }
+                            [] => false,
+                        }
+                }
+                (Syntax::Filter, ['+', rest @ ..]) => {
+                    let repeats = path.iter().take_while(|&&c| atom.matches(c)).count();
+
+                    (1..=repeats).any(|i| matches(rest, &path[i..], syntax))
+                }
+                _ => match path {
+                    [c, path_rest @ ..] => atom.matches(*c) && matches(rest, path_rest, syntax),
+                    [] => false,
+                },
+            }
+        }
+    }
+}
+
+/// Splits the atom at the start of the pattern from the rest of it.
+fn atom(pattern: &[char]) -> (Atom<'_>, &[char]) {
+    match pattern {
+        ['[', class @ ..] => match class_end(class) {
+            Some(end) => (Atom::Class(&class[..end]), &class[end + 1..]),
+            // An unterminated class is treated as a literal `[`
+            None => (Atom::Literal('['), class),
+        },
+        ['\\', escaped, rest @ ..] => (Atom::Literal(*escaped), rest),
+        [literal, rest @ ..] => (Atom::Literal(*literal), rest),
+        [] => unreachable!("atoms are only parsed from non-empty patterns"),
+    }
+}
+
+/// Returns the index of the `]` which closes the class.
+fn class_end(class: &[char]) -> Option<usize> {
+    // A `]` immediately after the opening bracket (or negation) is a literal
+    let start = match class {
+        ['!', ']', ..] | ['^', ']', ..] => 2,
+        ['!', ..] | ['^', ..] | [']', ..] => 1,
+        _ => 0,
+    };
+
+    class[start..]
+        .iter()
+        .position(|&c| c == ']')
+        .map(|i| i + start)
+}
+
+fn class_contains(class: &[char], c: char) -> bool {
+    let (negated, mut class) = match class {
+        ['!', rest @ ..] | ['^', rest @ ..] => (true, rest),
+        _ => (false, class),
+    };
+
+    let mut found = false;
+    while !class.is_empty() {
+        match class {
+            [start, '-', end, rest @ ..] => {
+                found |= *start <= c && c <= *end;
+                class = rest;
+            }
+            [single, rest @ ..] => {
+                found |= *single == c;
+                class = rest;
+            }
+            [] => unreachable!(),
+        }
+    }
+
+    found != negated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn match_wildcards() {
+        assert!(is_match("*.rs", "main.rs"));
+        assert!(!is_match("*.rs", "src/main.rs"));
+        assert!(is_match("**/*.rs", "main.rs"));
+        assert!(is_match("**/*.rs", "src/ci/github.rs"));
+        assert!(is_match("docs/**", "docs/book/intro.md"));
+        assert!(is_match("v?.0", "v1.0"));
+        assert!(!is_match("v?.0", "v10.0"));
+    }
+
+    #[test]
+    fn match_classes() {
+        assert!(is_match("v[12].[0-9]", "v2.7"));
+        assert!(!is_match("v[12].[0-9]", "v3.7"));
+        assert!(is_match("[!a-c]x", "dx"));
+        assert!(!is_match("[!a-c]x", "bx"));
+        assert!(is_match(r"\*", "*"));
+    }
+
+    #[test]
+    fn match_filters() {
+        // Examples from GitHub's filter pattern cheat sheet
+        assert!(is_filter_match("feature/*", "feature/my-branch"));
+        assert!(!is_filter_match("feature/*", "feature/your/branch"));
+        assert!(is_filter_match("feature/**", "feature/your/branch"));
+        assert!(is_filter_match("*feature", "mona-feature"));
+        assert!(is_filter_match("ver*", "version"));
+        assert!(is_filter_match("v2*", "v2.0.1"));
+        assert!(is_filter_match("v[12].[0-9]+.[0-9]+", "v1.10.1"));
+        assert!(is_filter_match("v[12].[0-9]+.[0-9]+", "v2.0.9"));
+        assert!(!is_filter_match("v[12].[0-9]+.[0-9]+", "v3.0.0"));
+        assert!(is_filter_match("*.jsx?", "page.js"));
+        assert!(is_filter_match("*.jsx?", "page.jsx"));
+        assert!(!is_filter_match("*.jsx?", "page.jsxx"));
+        assert!(is_filter_match("**.js", "js/index.js"));
+        assert!(is_filter_match("docs/**/*.md", "docs/README.md"));
+        assert!(is_filter_match("docs/**/*.md", "docs/mona/octocat.md"));
+    }
+}
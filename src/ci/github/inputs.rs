@@ -0,0 +1,205 @@
+//! The typed `inputs` of a workflow, such as those of `workflow_dispatch`.
+
+use std::collections::HashMap;
+
+use yaml_rust::Yaml;
+
+use super::{
+    expr::{object, Value},
+    scalar_to_string, YamlParseError,
+};
+
+/// Input is the definition of one of the inputs of a workflow.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Input {
+    pub id: String,
+    pub input_type: InputType,
+    pub required: bool,
+    pub default: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum InputType {
+    String,
+    Boolean,
+    Number,
+    /// One of a fixed set of options.
+    Choice(Vec<String>),
+    /// The name of a deployment environment, which is treated as a
+    /// string locally.
+    Environment,
+}
+
+/// Parses the `inputs` block of an event, such as `workflow_dispatch`.
+pub fn parse(yaml: &Yaml) -> Result<Vec<Input>, YamlParseError> {
+    let inputs = match yaml.as_hash() {
+        Some(inputs) => inputs,
+        None => return Ok(vec![]),
+    };
+
+    inputs
+        .iter()
+        .map(|(id, input)| {
+            let id = id.as_str().ok_or(YamlParseError::MissingField)?.to_string();
+            let input_type = match input["type"].as_str().unwrap_or("string") {
+                "string" => InputType::String,
+                "boolean" => InputType::Boolean,
+                "number" => InputType::Number,
+                "environment" => InputType::Environment,
+                "choice" => InputType::Choice(
+                    input["options"]
+                        .as_vec()
+                        .ok_or(YamlParseError::MissingField)?
+                        .iter()
+                        .map(|option| scalar_to_string(option).ok_or(YamlParseError::MissingField))
+                        .collect::<Result<_, _>>()?,
+                ),
+                other => return Err(YamlParseError::InvalidInputType(other.to_string())),
+            };
+
+            Ok(Input {
+                id,
+                input_type,
+                required: input["required"].as_bool().unwrap_or(false),
+                default: match &input["default"] {
+                    Yaml::BadValue => None,
+                    default => scalar_to_string(default),
+                },
+            })
+        })
+        .collect()
+}
+
+/// Returns the `inputs` context, from the values given on the command
+/// line or else the defaults of the inputs, converted to their types.
+/// Given values which aren't inputs of this workflow are ignored, since
+/// they may be meant for another workflow with the same trigger.
+pub fn context(inputs: &[Input], given: &HashMap<String, String>) -> Result<Value, String> {
+    let mut values = vec![];
+    for input in inputs {
+        let value = match given
+            .get(&input.id)
+            .or(input.default.as_ref())
+            .map(String::as_str)
+        {
+            Some(value) => value,
+            None if input.required => return Err(format!("Missing required input '{}'", input.id)),
+            None if input.input_type == InputType::Boolean => "false",
+            None => "",
+        };
+        let invalid = |expected: &str| {
+            format!(
+                "Invalid value '{}' for input '{}', expected {}",
+                value, input.id, expected
+            )
+        };
+
+        let value = match &input.input_type {
+            InputType::Boolean => match value {
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                _ => return Err(invalid("`true` or `false`")),
+            },
+            InputType::Number if value.is_empty() => Value::Null,
+            InputType::Number => {
+                Value::Number(value.trim().parse().map_err(|_| invalid("a number"))?)
+            }
+            InputType::Choice(options) if !options.iter().any(|option| option == value) => {
+                return Err(invalid(&format!("one of '{}'", options.join("', '"))))
+            }
+            InputType::String | InputType::Choice(_) | InputType::Environment => {
+                Value::String(value.to_string())
+            }
+        };
+
+        values.push((input.id.as_str(), value));
+    }
+
+    Ok(object(values))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use yaml_rust::YamlLoader;
+
+    fn inputs() -> Vec<Input> {
+        let yaml = YamlLoader::load_from_str(
+            r#"
+version:
+  required: true
+dry-run:
+  type: boolean
+  default: true
+level:
+  type: choice
+  options: [debug, info]
+  default: info
+retries:
+  type: number
+"#,
+        )
+        .unwrap()
+        .pop()
+        .unwrap();
+
+        parse(&yaml).unwrap()
+    }
+
+    #[test]
+    fn inputs_context() {
+        let given = vec![("version".to_string(), "1.2.3".to_string())]
+            .into_iter()
+            .collect();
+        let context = context(&inputs(), &given).unwrap();
+
+        assert_eq!(
+            Value::String("1.2.3".into()),
+            context.get(&"version".into())
+        );
+        assert_eq!(Value::Bool(true), context.get(&"dry-run".into()));
+        assert_eq!(Value::String("info".into()), context.get(&"level".into()));
+        assert_eq!(Value::Null, context.get(&"retries".into()));
+    }
+
+    #[test]
+    fn inputs_context_ignores_undeclared() {
+        let given = vec![
+            ("version".to_string(), "1.2.3".to_string()),
+            ("other".to_string(), "1".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        let context = context(&inputs(), &given).unwrap();
+
+        assert_eq!(Value::Null, context.get(&"other".into()));
+    }
+
+    #[test]
+    fn inputs_context_invalid() {
+        let context = |given: &[(&str, &str)]| {
+            let given = given
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            super::context(&inputs(), &given)
+        };
+
+        assert_eq!(
+            Err("Missing required input 'version'".to_string()),
+            context(&[])
+        );
+        assert_eq!(
+            Err("Invalid value 'yes' for input 'dry-run', expected `true` or `false`".to_string()),
+            context(&[("version", "1"), ("dry-run", "yes")])
+        );
+        assert_eq!(
+            Err(
+                "Invalid value 'trace' for input 'level', expected one of 'debug', 'info'"
+                    .to_string()
+            ),
+            context(&[("version", "1"), ("level", "trace")])
+        );
+    }
+}
@@ -0,0 +1,97 @@
+//! Matching the `runs-on` labels of a job against the local machine.
+
+use std::collections::HashMap;
+
+use yaml_rust::Yaml;
+
+use super::YamlParseError;
+
+/// Parses `runs-on`, which may be a single label, an array of labels
+/// which a runner must all have, or a runner group with `labels`.
+pub fn parse(yaml: &Yaml) -> Result<Vec<String>, YamlParseError> {
+    match yaml {
+        Yaml::BadValue => Ok(vec![]),
+        Yaml::String(label) => Ok(vec![label.clone()]),
+        Yaml::Array(labels) => labels
+            .iter()
+            .map(|label| {
+                label
+                    .as_str()
+                    .map(str::to_string)
+                    .ok_or(YamlParseError::MissingField)
+            })
+            .collect(),
+        Yaml::Hash(_) => parse(&yaml["labels"]),
+        _ => Err(YamlParseError::MissingField),
+    }
+}
+
+/// Returns the first label which means the job must run on a different
+/// operating system than this one. Labels which don't imply an
+/// operating system, such as `self-hosted`, match any machine.
+///
+/// `custom` maps self-hosted runner labels to the operating system
+/// they imply, as one of `linux`, `macos` or `windows`.
+pub fn unsupported<'a>(labels: &'a [String], custom: &HashMap<String, String>) -> Option<&'a str> {
+    let host = std::env::consts::OS;
+
+    labels
+        .iter()
+        .find(|label| os(label, custom).is_some_and(|os| os != host))
+        .map(String::as_str)
+}
+
+/// Returns the operating system a label implies, in the form of
+/// `std::env::consts::OS`.
+fn os(label: &str, custom: &HashMap<String, String>) -> Option<String> {
+    if let Some(os) = custom.get(label) {
+        return Some(os.to_lowercase());
+    }
+
+    let label = label.to_lowercase();
+    if label.starts_with("ubuntu") || label == "linux" {
+        Some("linux".to_string())
+    } else if label.starts_with("windows") {
+        Some("windows".to_string())
+    } else if label.starts_with("macos") {
+        Some("macos".to_string())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn label_os() {
+        let custom = vec![("gpu-box".to_string(), "Windows".to_string())]
+            .into_iter()
+            .collect();
+
+        assert_eq!(Some("linux".to_string()), os("ubuntu-22.04", &custom));
+        assert_eq!(Some("linux".to_string()), os("Linux", &custom));
+        assert_eq!(Some("windows".to_string()), os("windows-latest", &custom));
+        assert_eq!(Some("macos".to_string()), os("macOS", &custom));
+        assert_eq!(Some("windows".to_string()), os("gpu-box", &custom));
+        assert_eq!(None, os("self-hosted", &custom));
+    }
+
+    #[test]
+    fn unsupported_labels() {
+        let labels = |labels: &[&str]| labels.iter().map(|l| l.to_string()).collect::<Vec<_>>();
+        let custom = HashMap::new();
+        let other = if std::env::consts::OS == "windows" {
+            "ubuntu-latest"
+        } else {
+            "windows-latest"
+        };
+
+        assert_eq!(None, unsupported(&labels(&["self-hosted", "x64"]), &custom));
+        assert_eq!(
+            Some(other),
+            unsupported(&labels(&["self-hosted", other]), &custom)
+        );
+    }
+}
@@ -0,0 +1,218 @@
+use yaml_rust::Yaml;
+
+use super::{
+    expr::{object, Value},
+    scalar_to_string, YamlParseError,
+};
+
+/// A single combination of matrix values, in the order the keys
+/// were declared in the workflow.
+pub type Combination = Vec<(String, Yaml)>;
+
+/// Expands `strategy.matrix` into the combinations GitHub Actions
+/// would create jobs for. Jobs without a matrix have no
+/// combinations.
+///
+/// See https://docs.github.com/en/actions/using-jobs/using-a-matrix-for-your-jobs
+pub fn parse(yaml: &Yaml) -> Result<Vec<Combination>, YamlParseError> {
+    let matrix = match yaml.as_hash() {
+        Some(matrix) => matrix,
+        // A matrix may also be generated by an expression, for example
+        // using `fromJSON`, which we don't support.
+        None => return Ok(vec![]),
+    };
+
+    let mut combinations: Vec<Combination> = vec![vec![]];
+    let mut original_keys = vec![];
+    for (key, values) in matrix {
+        let key = key.as_str().ok_or(YamlParseError::MissingField)?;
+        if key == "include" || key == "exclude" {
+            continue;
+        }
+        let values = values.as_vec().ok_or(YamlParseError::MissingField)?;
+
+        original_keys.push(key.to_string());
+        combinations = combinations
+            .into_iter()
+            .flat_map(|combination| {
+                values.iter().map(move |value| {
+                    let mut combination = combination.clone();
+                    combination.push((key.to_string(), value.clone()));
+                    combination
+                })
+            })
+            .collect();
+    }
+    if original_keys.is_empty() {
+        combinations.clear();
+    }
+
+    for exclude in entries(&yaml["exclude"])? {
+        combinations.retain(|combination| !is_match(combination, &exclude));
+    }
+
+    let original_count = combinations.len();
+    for include in entries(&yaml["include"])? {
+        // An include entry extends every original combination it can be added to
+        // without overwriting an original value, and otherwise becomes a new
+        // combination of its own.
+        let applies_to = |combination: &Combination| {
+            include.iter().all(|(key, value)| {
+                !original_keys.contains(key) || get(combination, key) == Some(value)
+            })
+        };
+
+        let mut added = false;
+        for combination in combinations.iter_mut().take(original_count) {
+            if applies_to(combination) {
+                for (key, value) in &include {
+                    set(combination, key, value);
+                }
+                added = true;
+            }
+        }
+
+        if !added {
+            combinations.push(include);
+        }
+    }
+
+    Ok(combinations)
+}
+
+/// Parses the entries of an `include` or `exclude` list.
+fn entries(yaml: &Yaml) -> Result<Vec<Combination>, YamlParseError> {
+    let entries = match yaml.as_vec() {
+        Some(entries) => entries,
+        None => return Ok(vec![]),
+    };
+
+    entries
+        .iter()
+        .map(|entry| {
+            entry
+                .as_hash()
+                .ok_or(YamlParseError::MissingField)?
+                .iter()
+                .map(|(key, value)| {
+                    let key = key.as_str().ok_or(YamlParseError::MissingField)?;
+                    Ok((key.to_string(), value.clone()))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Returns true if every value in the entry is present in the
+/// combination.
+fn is_match(combination: &Combination, entry: &Combination) -> bool {
+    entry
+        .iter()
+        .all(|(key, value)| get(combination, key) == Some(value))
+}
+
+pub fn get<'a>(combination: &'a Combination, key: &str) -> Option<&'a Yaml> {
+    combination
+        .iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, value)| value)
+}
+
+fn set(combination: &mut Combination, key: &str, value: &Yaml) {
+    match combination.iter_mut().find(|(k, _)| k == key) {
+        Some((_, v)) => *v = value.clone(),
+        None => combination.push((key.to_string(), value.clone())),
+    }
+}
+
+/// Returns the label GitHub Actions appends to the job name for a
+/// combination, for example `(stable, no-std)`.
+pub fn label(combination: &Combination) -> String {
+    let values = combination
+        .iter()
+        .filter_map(|(_, value)| scalar_to_string(value))
+        .collect::<Vec<String>>();
+
+    format!("({})", values.join(", "))
+}
+
+/// Returns the scalar values of a combination as strings, for
+/// matching against the filters given by the user.
+pub fn values(combination: &Combination) -> Vec<(String, String)> {
+    combination
+        .iter()
+        .filter_map(|(key, value)| scalar_to_string(value).map(|value| (key.clone(), value)))
+        .collect()
+}
+
+/// Returns the `matrix` context for a combination.
+pub fn context(combination: &Combination) -> Value {
+    object(
+        combination
+            .iter()
+            .map(|(key, value)| (key.as_str(), Value::from(value))),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use yaml_rust::YamlLoader;
+
+    fn combinations(input: &str) -> Vec<Combination> {
+        let yaml = YamlLoader::load_from_str(input).unwrap().pop().unwrap();
+
+        parse(&yaml).unwrap()
+    }
+
+    #[test]
+    fn parse_matrix_product() {
+        let combinations = combinations(
+            r#"
+rust: [stable, nightly]
+features: [default, full]
+exclude:
+  - rust: nightly
+    features: default
+"#,
+        );
+
+        assert_eq!(
+            vec!["(stable, default)", "(stable, full)", "(nightly, full)"],
+            combinations.iter().map(label).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn parse_matrix_include() {
+        let combinations = combinations(
+            r#"
+fruit: [apple, pear]
+animal: [cat, dog]
+include:
+  - color: green
+  - color: pink
+    animal: cat
+  - fruit: apple
+    shape: circle
+  - fruit: banana
+  - fruit: banana
+    animal: cat
+"#,
+        );
+
+        // This is the example from the GitHub Actions documentation
+        assert_eq!(
+            vec![
+                "(apple, cat, pink, circle)",
+                "(apple, dog, green, circle)",
+                "(pear, cat, pink)",
+                "(pear, dog, green)",
+                "(banana)",
+                "(banana, cat)",
+            ],
+            combinations.iter().map(label).collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use crate::ci::Shell;
+
+use super::YamlParseError;
+
+/// Parses the value of a `shell` key into the invocation GitHub
+/// Actions would use for it.
+///
+/// See https://docs.github.com/en/actions/reference/workflow-syntax-for-github-actions#jobsjob_idstepsshell
+pub fn parse(name: &str) -> Result<Shell, YamlParseError> {
+    let (args, extension): (&[&str], &str) = match name {
+        "bash" => (
+            &["bash", "--noprofile", "--norc", "-eo", "pipefail", "{0}"],
+            ".sh",
+        ),
+        "sh" => (&["sh", "-e", "{0}"], ".sh"),
+        "python" => (&["python", "{0}"], ".py"),
+        "pwsh" => (&["pwsh", "-command", ". '{0}'"], ".ps1"),
+        "powershell" => (&["powershell", "-command", ". '{0}'"], ".ps1"),
+        "cmd" => (
+            &["cmd", "/D", "/E:ON", "/V:OFF", "/S", "/C", "CALL \"{0}\""],
+            ".cmd",
+        ),
+        custom => {
+            // Custom shells are a command template, where `{0}` is
+            // replaced with the path to the script.
+            if !custom.contains("{0}") {
+                return Err(YamlParseError::InvalidShell(custom.to_string()));
+            }
+
+            return Ok(Shell {
+                args: custom.split_whitespace().map(String::from).collect(),
+                extension: "",
+            });
+        }
+    };
+
+    Ok(Shell {
+        args: args.iter().map(|s| s.to_string()).collect(),
+        extension,
+    })
+}
+
+/// Returns the shell GitHub Actions uses for steps which don't
+/// specify one.
+///
+/// On Linux and macOS runners this is bash if it is installed, falling
+/// back to sh. Notably, this is not the same invocation as an explicit
+/// `shell: bash`, which additionally sets `pipefail`.
+pub fn default() -> Shell {
+    #[cfg(windows)]
+    let shell = Shell {
+        args: vec!["pwsh".into(), "-command".into(), ". '{0}'".into()],
+        extension: ".ps1",
+    };
+    #[cfg(not(windows))]
+    let shell = unix_default(is_on_path("bash"));
+
+    shell
+}
+
+/// Returns the default shell of a Linux or macOS runner, or of a job's
+/// container, depending on whether it has bash installed.
+pub fn unix_default(has_bash: bool) -> Shell {
+    let program = if has_bash { "bash" } else { "sh" };
+
+    Shell {
+        args: vec![program.into(), "-e".into(), "{0}".into()],
+        extension: ".sh",
+    }
+}
+
+#[cfg(not(windows))]
+fn is_on_path(program: &str) -> bool {
+    std::env::var_os("PATH")
+        .map(|path| std::env::split_paths(&path).any(|dir| dir.join(program).is_file()))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_builtin_shells() {
+        assert_eq!(
+            vec!["bash", "--noprofile", "--norc", "-eo", "pipefail", "{0}"],
+            parse("bash").unwrap().args
+        );
+        assert_eq!(".py", parse("python").unwrap().extension);
+    }
+
+    #[test]
+    fn parse_custom_shell() {
+        let shell = parse("perl {0}").unwrap();
+
+        assert_eq!(vec!["perl", "{0}"], shell.args);
+        assert_eq!("", shell.extension);
+
+        assert!(parse("perl").is_err());
+    }
+}
@@ -0,0 +1,131 @@
+//! Shims which run well-known actions as local commands, since the
+//! actions themselves can't run outside of GitHub Actions.
+
+use std::collections::HashMap;
+
+use super::{shell, CiConfigJobStep, YamlParseError};
+
+/// The built-in shims, as pairs of an action and the command which
+/// replaces it, or `None` if the action isn't needed locally. Commands
+/// may refer to the step's `with` values through the `inputs` context,
+/// and to the action's ref through `env.GITHUB_ACTION_REF`.
+const BUILTIN: &[(&str, Option<&str>)] = &[
+    ("actions/checkout", None),
+    ("actions/cache", None),
+    ("Swatinem/rust-cache", None),
+    (
+        "actions-rs/cargo",
+        Some(
+            "${{ inputs.use-cross == 'true' && 'cross' || 'cargo' }}\
+             ${{ inputs.toolchain && format(' +{0}', inputs.toolchain) || '' }} \
+             ${{ inputs.command }} ${{ inputs.args }}",
+        ),
+    ),
+    (
+        "actions-rs/toolchain",
+        Some(
+            "${{ inputs.toolchain && \
+             format('echo \"RUSTUP_TOOLCHAIN={0}\" >> \"$GITHUB_ENV\"', inputs.toolchain) || '' }}",
+        ),
+    ),
+    (
+        "dtolnay/rust-toolchain",
+        Some(
+            "echo \"RUSTUP_TOOLCHAIN=${{ inputs.toolchain || env.GITHUB_ACTION_REF }}\" \
+             >> \"$GITHUB_ENV\"",
+        ),
+    ),
+];
+
+/// Shims maps actions, without their ref, to the command which runs
+/// in their place.
+pub struct Shims {
+    shims: HashMap<String, Option<String>>,
+}
+
+impl Shims {
+    /// Creates the built-in shims, extended with those from the user's
+    /// config, which take precedence.
+    pub fn new(custom: &HashMap<String, Option<String>>) -> Self {
+        let mut shims = BUILTIN
+            .iter()
+            .map(|(action, run)| (action.to_string(), run.map(str::to_string)))
+            .collect::<HashMap<_, _>>();
+        shims.extend(custom.iter().map(|(k, v)| (k.clone(), v.clone())));
+
+        Self { shims }
+    }
+
+    /// Replaces the action the step uses with its shim, if it has one.
+    /// The step then runs the shim's command with bash, or does
+    /// nothing if the action isn't needed locally.
+    pub fn apply(&self, step: &mut CiConfigJobStep) -> Result<(), YamlParseError> {
+        let uses = match &step.uses {
+            Some(uses) => uses,
+            None => return Ok(()),
+        };
+        let (action, git_ref) = uses.split_once('@').unwrap_or((uses, ""));
+        let run = match self.shims.get(action) {
+            Some(run) => run,
+            None => return Ok(()),
+        };
+
+        match run {
+            Some(run) => {
+                step.run = Some(run.clone());
+                step.shell = Some(shell::parse("bash")?);
+                step.env
+                    .insert("GITHUB_ACTION_REF".into(), git_ref.to_string());
+                step.inputs.push(std::mem::take(&mut step.with));
+            }
+            None => step.noop = true,
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::*;
+    use crate::ci::github::CiConfig;
+
+    #[test]
+    fn apply_shims() {
+        let config = CiConfig::try_from(
+            r#"
+on: push
+jobs:
+  build:
+    runs-on: ubuntu-latest
+    steps:
+      - uses: actions/checkout@v4
+      - uses: dtolnay/rust-toolchain@nightly
+      - uses: actions-rs/cargo@v1
+        with:
+          command: clippy
+          args: --all-features
+      - uses: actions/setup-node@v4
+"#,
+        )
+        .unwrap();
+        let custom = vec![("actions/setup-node".to_string(), None)]
+            .into_iter()
+            .collect();
+        let shims = Shims::new(&custom);
+
+        let mut steps = config.jobs.into_iter().next().unwrap().1.steps;
+        for step in &mut steps {
+            shims.apply(step).unwrap();
+        }
+
+        assert!(steps[0].noop);
+        assert_eq!(None, steps[0].run);
+        assert_eq!("nightly", steps[1].env["GITHUB_ACTION_REF"]);
+        assert!(steps[2].run.as_deref().unwrap().contains("inputs.command"));
+        assert_eq!("clippy", steps[2].inputs[0]["command"]);
+        assert!(steps[3].noop);
+    }
+}
@@ -1,14 +1,42 @@
-use serde::Deserialize;
-use std::collections::HashMap;
+use serde::{
+    de::{MapAccess, Visitor},
+    Deserialize, Deserializer,
+};
+use std::{collections::HashMap, fmt};
+
+use super::Shell;
+mod variables;
+pub use variables::Project;
+use variables::Variable;
+
+/// The stages jobs run in when the configuration doesn't list them.
+const DEFAULT_STAGES: [&str; 3] = ["build", "test", "deploy"];
 
 #[derive(Deserialize)]
 pub struct CiConfig {
     #[allow(dead_code)]
     image: Option<String>,
-    #[allow(dead_code)]
+    /// The stages jobs run in, in order, besides `.pre` and `.post`.
     stages: Option<Vec<String>>,
-    #[serde(flatten)]
-    pub(super) jobs: HashMap<String, CiConfigJob>,
+    /// The keys every job inherits unless it sets its own.
+    #[serde(default)]
+    default: JobDefaults,
+    /// The legacy, top-level equivalents of the keys in `default`.
+    before_script: Option<Vec<String>>,
+    after_script: Option<Vec<String>>,
+    /// The variables every job is run with.
+    #[serde(default)]
+    variables: HashMap<String, Variable>,
+    /// The jobs in the order they are declared.
+    #[serde(flatten, deserialize_with = "ordered_jobs")]
+    pub(super) jobs: Vec<(String, CiConfigJob)>,
+}
+
+/// The `default` block, whose keys apply to jobs which don't set them.
+#[derive(Default, Deserialize)]
+struct JobDefaults {
+    before_script: Option<Vec<String>>,
+    after_script: Option<Vec<String>>,
 }
 
 /// All fields which aren't explicitly configured in this struct are
@@ -21,6 +49,267 @@ pub struct CiConfig {
 #[derive(Deserialize)]
 pub struct CiConfigJob {
     pub(super) script: Option<Vec<String>>,
+    /// Commands run before `script`, in the same shell.
+    before_script: Option<Vec<String>>,
+    /// Commands run after `script` in a new shell, even if it failed.
+    after_script: Option<Vec<String>>,
+    /// The stage the job runs in, which is `test` by default.
+    stage: Option<String>,
+    /// The jobs which must complete before this one. Without this, a
+    /// job runs after every job in the earlier stages.
+    needs: Option<Vec<Need>>,
+    /// The variables the job is run with, besides the global ones.
+    #[serde(default)]
+    variables: HashMap<String, Variable>,
+}
+
+/// An entry in `needs`, which is either a job's name or a hash with
+/// the job's name and options such as `artifacts`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Need {
+    Job(String),
+    Hash { job: String },
+}
+
+impl Need {
+    fn job(&self) -> &str {
+        match self {
+            Need::Job(job) | Need::Hash { job } => job,
+        }
+    }
+}
+
+impl CiConfigJob {
+    /// Returns the stage the job runs in.
+    pub fn stage(&self) -> &str {
+        self.stage.as_deref().unwrap_or("test")
+    }
+}
+
+impl CiConfig {
+    /// Returns the stages in the order they run, including `.pre` and
+    /// `.post`, which always run first and last.
+    pub fn stages(&self) -> Vec<&str> {
+        let stages = match &self.stages {
+            Some(stages) => stages.iter().map(String::as_str).collect(),
+            None => DEFAULT_STAGES.to_vec(),
+        };
+
+        std::iter::once(".pre")
+            .chain(stages)
+            .chain(std::iter::once(".post"))
+            .collect()
+    }
+
+    /// Returns the jobs which can run, in the order they should run.
+    /// Jobs run stage by stage, in the order they are declared, except
+    /// that a job with `needs` runs once the jobs it needs have, so
+    /// it may run before the rest of the earlier stages.
+    pub fn ordered_jobs(&self) -> Vec<(&str, &CiConfigJob)> {
+        let stages = self.stages();
+        let stage_index = |job: &CiConfigJob| {
+            stages
+                .iter()
+                .position(|stage| *stage == job.stage())
+                .unwrap_or(stages.len())
+        };
+
+        let mut remaining = self
+            .jobs
+            .iter()
+            // Hidden jobs are only templates for other jobs to extend
+            .filter(|(name, job)| job.script.is_some() && !name.starts_with('.'))
+            .map(|(name, job)| (name.as_str(), job))
+            .collect::<Vec<_>>();
+        remaining.sort_by_key(|(_, job)| stage_index(job));
+
+        let mut ordered: Vec<(&str, &CiConfigJob)> = vec![];
+        while !remaining.is_empty() {
+            // Cycles are reported by `check`, so any remaining jobs then
+            // run in stage order
+            let ready = remaining
+                .iter()
+                .position(|(name, job)| {
+                    self.needs(name, job)
+                        .iter()
+                        .all(|need| ordered.iter().any(|(done, _)| done == need))
+                })
+                .unwrap_or(0);
+
+            ordered.push(remaining.remove(ready));
+        }
+
+        ordered
+    }
+
+    /// Returns the names of the jobs which must complete before the
+    /// job runs. These are its `needs` if it has them, and otherwise
+    /// every job in the earlier stages.
+    pub fn needs(&self, name: &str, job: &CiConfigJob) -> Vec<String> {
+        if let Some(needs) = &job.needs {
+            return needs.iter().map(|need| need.job().to_string()).collect();
+        }
+
+        let stages = self.stages();
+        let stage_index = |job: &CiConfigJob| stages.iter().position(|stage| *stage == job.stage());
+        let index = stage_index(job);
+        self.jobs
+            .iter()
+            .filter(|(other, other_job)| {
+                other != name
+                    && other_job.script.is_some()
+                    && !other.starts_with('.')
+                    && stage_index(other_job) < index
+            })
+            .map(|(other, _)| other.clone())
+            .collect()
+    }
+
+    /// Returns an error if a job is in a stage which isn't listed, or
+    /// needs a job which doesn't exist or which needs it in turn.
+    pub fn check(&self) -> Result<(), String> {
+        let stages = self.stages();
+        let jobs = self
+            .jobs
+            .iter()
+            .filter(|(name, job)| job.script.is_some() && !name.starts_with('.'))
+            .collect::<Vec<_>>();
+
+        for (name, job) in &jobs {
+            if !stages.contains(&job.stage()) {
+                return Err(format!(
+                    "Job '{}' is in stage '{}', which isn't listed in `stages`",
+                    name,
+                    job.stage()
+                ));
+            }
+            for need in self.needs(name, job) {
+                if !jobs.iter().any(|(other, _)| *other == need) {
+                    return Err(format!("Job '{}' needs unknown job '{}'", name, need));
+                }
+            }
+        }
+
+        let ordered = self.ordered_jobs();
+        for (index, (name, job)) in ordered.iter().enumerate() {
+            let later_need = self.needs(name, job).into_iter().find(|need| {
+                ordered[index..]
+                    .iter()
+                    .any(|(other, _)| *other == need.as_str())
+            });
+            if let Some(need) = later_need {
+                return Err(format!(
+                    "Job '{}' needs '{}', which can't run before it",
+                    name, need
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the `before_script` of a job, falling back to the one
+    /// in `default` and then the legacy top-level one.
+    pub fn before_script<'a>(&'a self, job: &'a CiConfigJob) -> &'a [String] {
+        job.before_script
+            .as_ref()
+            .or(self.default.before_script.as_ref())
+            .or(self.before_script.as_ref())
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns the `after_script` of a job, falling back to the one
+    /// in `default` and then the legacy top-level one.
+    pub fn after_script<'a>(&'a self, job: &'a CiConfigJob) -> &'a [String] {
+        job.after_script
+            .as_ref()
+            .or(self.default.after_script.as_ref())
+            .or(self.after_script.as_ref())
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns the environment variables of a job: those GitLab
+    /// predefines, overridden by the global `variables` and then the
+    /// job's own, with references between them expanded.
+    pub fn variables(
+        &self,
+        name: &str,
+        job: &CiConfigJob,
+        project: &Project,
+    ) -> HashMap<String, String> {
+        let predefined = variables::predefined(name, job.stage(), project)
+            .into_iter()
+            .map(|(name, value)| (name, (value, false)))
+            .collect();
+
+        variables::resolve(vec![
+            predefined,
+            variables::values(&self.variables),
+            variables::values(&job.variables),
+        ])
+    }
+}
+
+/// Deserializes the jobs, keeping the order they are declared in.
+fn ordered_jobs<'de, D>(deserializer: D) -> Result<Vec<(String, CiConfigJob)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct JobsVisitor;
+
+    impl<'de> Visitor<'de> for JobsVisitor {
+        type Value = Vec<(String, CiConfigJob)>;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "a map of jobs")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut jobs = vec![];
+            while let Some(entry) = map.next_entry()? {
+                jobs.push(entry);
+            }
+
+            Ok(jobs)
+        }
+    }
+
+    deserializer.deserialize_map(JobsVisitor)
+}
+
+/// Returns the shell script which runs the lines of a job one after
+/// another, as the GitLab runner does. Each line is echoed before it
+/// runs, and if one fails, it is reported along with its exit code.
+pub fn script(lines: &[String]) -> String {
+    let mut script = vec![
+        "belay_line=".to_string(),
+        r#"trap 'belay_status=$?; if [ "$belay_status" -ne 0 ] && [ -n "$belay_line" ]; then printf "\"%s\" failed with exit code %s\n" "$belay_line" "$belay_status"; fi' EXIT"#
+            .to_string(),
+    ];
+    for line in lines {
+        script.push(format!("belay_line={}", quote(line)));
+        script.push(format!(r"printf '%s\n' {}", quote(&format!("$ {}", line))));
+        script.push(line.clone());
+    }
+    // The script succeeded, so there is no line to report
+    script.push("belay_line=".to_string());
+
+    script.join("\n")
+}
+
+/// Quotes a string for the shell.
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Returns the shell a job's commands run in, as one session which
+/// stops at the first command to fail.
+pub fn shell() -> Shell {
+    Shell {
+        args: vec!["sh".into(), "-e".into(), "{0}".into()],
+        extension: ".sh",
+    }
 }
 
 #[cfg(test)]
@@ -37,7 +326,7 @@ mod tests {
 
         assert_eq!(
             5,
-            gitlab_ci_config.jobs.values().fold(0, |mut acc, job| {
+            gitlab_ci_config.jobs.iter().fold(0, |mut acc, (_, job)| {
                 acc += job
                     .script
                     .as_ref()
@@ -49,4 +338,120 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn run_script() -> Result<()> {
+        let script = script(&[
+            "export GREETING=hi".to_string(),
+            r#"echo "$GREETING, it's me""#.to_string(),
+            "exit 3".to_string(),
+            "echo unreachable".to_string(),
+        ]);
+
+        let output = std::process::Command::new("sh")
+            .arg("-e")
+            .arg("-c")
+            .arg(&script)
+            .output()?;
+
+        assert_eq!(Some(3), output.status.code());
+        assert_eq!(
+            r#"$ export GREETING=hi
+$ echo "$GREETING, it's me"
+hi, it's me
+$ exit 3
+"exit 3" failed with exit code 3
+"#,
+            String::from_utf8(output.stdout)?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn order_jobs() -> Result<()> {
+        let gitlab_ci_config = serde_yaml::from_str::<CiConfig>(
+            r#"
+test:
+  script: [cargo test]
+.template:
+  script: [echo hidden]
+build:
+  stage: build
+  script: [cargo build]
+cleanup:
+  stage: .post
+  script: [echo done]
+"#,
+        )?;
+
+        let names = gitlab_ci_config
+            .ordered_jobs()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["build", "test", "cleanup"], names);
+        assert_eq!(
+            vec!["test".to_string(), "build".to_string()],
+            gitlab_ci_config.needs("cleanup", &gitlab_ci_config.jobs[3].1)
+        );
+        assert!(gitlab_ci_config.check().is_ok());
+
+        let cyclic = serde_yaml::from_str::<CiConfig>(
+            r#"
+a:
+  needs: [b]
+  script: [echo a]
+b:
+  needs: [{job: a}]
+  script: [echo b]
+"#,
+        )?;
+        assert!(cyclic.check().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn inherit_scripts() -> Result<()> {
+        let gitlab_ci_config = serde_yaml::from_str::<CiConfig>(
+            r#"
+before_script:
+  - legacy setup
+after_script:
+  - legacy cleanup
+default:
+  before_script:
+    - default setup
+
+build:
+  script:
+    - cargo build
+test:
+  before_script: []
+  after_script:
+    - cleanup
+  script:
+    - cargo test
+"#,
+        )?;
+        let build = &gitlab_ci_config.jobs[0].1;
+        let test = &gitlab_ci_config.jobs[1].1;
+
+        assert_eq!(
+            &["default setup".to_string()],
+            gitlab_ci_config.before_script(build)
+        );
+        assert_eq!(
+            &["legacy cleanup".to_string()],
+            gitlab_ci_config.after_script(build)
+        );
+        assert!(gitlab_ci_config.before_script(test).is_empty());
+        assert_eq!(
+            &["cleanup".to_string()],
+            gitlab_ci_config.after_script(test)
+        );
+
+        Ok(())
+    }
 }
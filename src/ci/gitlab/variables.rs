@@ -0,0 +1,319 @@
+//! GitLab CI/CD variables, from the `variables` blocks and those GitLab
+//! predefines for every job.
+
+use std::{collections::HashMap, path::PathBuf};
+
+use serde::Deserialize;
+use serde_yaml::Value;
+
+use crate::git;
+
+/// Variable is an entry in a `variables` block, which is either its
+/// value or a hash with its `value` and a `description`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum Variable {
+    Hash {
+        value: Option<Value>,
+        /// Whether references to other variables in the value are
+        /// expanded.
+        #[serde(default = "enabled")]
+        expand: bool,
+    },
+    Value(Value),
+}
+
+fn enabled() -> bool {
+    true
+}
+
+impl Variable {
+    /// Returns the variable's value, and whether it may be expanded.
+    fn value(&self) -> (String, bool) {
+        match self {
+            Variable::Hash { value, expand } => {
+                (value.as_ref().map(scalar).unwrap_or_default(), *expand)
+            }
+            Variable::Value(value) => (scalar(value), true),
+        }
+    }
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Number(n) => n.to_string(),
+        Value::Bool(b) => b.to_string(),
+        _ => String::new(),
+    }
+}
+
+/// Project is what the predefined variables say about the local
+/// repository, looked up once for all of a pipeline's jobs.
+pub struct Project {
+    dir: PathBuf,
+    branch: String,
+    sha: String,
+    path: String,
+    user_name: String,
+}
+
+impl Project {
+    /// Looks up the project from the repository belay is run in.
+    pub fn local() -> Self {
+        Self {
+            dir: git::find_root().unwrap_or_default(),
+            branch: git::current_branch(),
+            sha: git::head_sha().unwrap_or_default(),
+            path: git::repository().unwrap_or_default(),
+            user_name: git::user_name().unwrap_or_default(),
+        }
+    }
+}
+
+/// Returns the variables GitLab predefines for a job in the project.
+pub fn predefined(job: &str, stage: &str, project: &Project) -> Vec<(String, String)> {
+    let namespace = project
+        .path
+        .rsplit_once('/')
+        .map(|(namespace, _)| namespace.to_string())
+        .unwrap_or_default();
+    let project_name = project
+        .dir
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    vec![
+        ("CI", "true".to_string()),
+        ("GITLAB_CI", "true".to_string()),
+        ("CI_SERVER", "yes".to_string()),
+        ("CI_CONFIG_PATH", ".gitlab-ci.yml".to_string()),
+        ("CI_PROJECT_DIR", project.dir.to_string_lossy().into_owned()),
+        ("CI_PROJECT_NAME", project_name),
+        ("CI_PROJECT_NAMESPACE", namespace),
+        ("CI_PROJECT_PATH", project.path.clone()),
+        ("CI_COMMIT_SHA", project.sha.clone()),
+        ("CI_COMMIT_SHORT_SHA", project.sha.chars().take(8).collect()),
+        ("CI_COMMIT_BRANCH", project.branch.clone()),
+        ("CI_COMMIT_REF_NAME", project.branch.clone()),
+        ("CI_COMMIT_REF_SLUG", slug(&project.branch)),
+        ("CI_JOB_ID", "1".to_string()),
+        ("CI_JOB_NAME", job.to_string()),
+        ("CI_JOB_STAGE", stage.to_string()),
+        ("CI_PIPELINE_ID", "1".to_string()),
+        ("CI_PIPELINE_IID", "1".to_string()),
+        ("CI_PIPELINE_SOURCE", "push".to_string()),
+        ("GITLAB_USER_NAME", project.user_name.clone()),
+    ]
+    .into_iter()
+    .map(|(k, v)| (k.to_string(), v))
+    .collect()
+}
+
+/// Returns the ref in the form GitLab uses in URLs and domain names:
+/// lowercased, with anything but letters and digits replaced by `-`,
+/// and at most 63 bytes long.
+fn slug(git_ref: &str) -> String {
+    let slug = git_ref
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .take(63)
+        .collect::<String>();
+
+    slug.trim_matches('-').to_string()
+}
+
+/// Resolves the variables of a job, expanding the references in their
+/// values. Later sets of variables take precedence, so the predefined
+/// variables should come first, then the global and job variables.
+pub fn resolve(
+    sets: impl IntoIterator<Item = Vec<(String, (String, bool))>>,
+) -> HashMap<String, String> {
+    let mut raw = HashMap::new();
+    for set in sets {
+        raw.extend(set);
+    }
+
+    let mut resolver = Resolver {
+        raw: &raw,
+        resolved: HashMap::new(),
+        resolving: vec![],
+    };
+    let names = raw.keys().cloned().collect::<Vec<_>>();
+    for name in &names {
+        resolver.get(name);
+    }
+
+    resolver.resolved
+}
+
+/// Returns the values of the entries of a `variables` block, along
+/// with whether each may be expanded.
+pub fn values(variables: &HashMap<String, Variable>) -> Vec<(String, (String, bool))> {
+    variables
+        .iter()
+        .map(|(name, variable)| (name.clone(), variable.value()))
+        .collect()
+}
+
+/// Resolver expands variables on demand, so they may refer to each
+/// other in any order.
+struct Resolver<'a> {
+    raw: &'a HashMap<String, (String, bool)>,
+    resolved: HashMap<String, String>,
+    /// The variables being expanded, to detect references in a cycle.
+    resolving: Vec<String>,
+}
+
+impl Resolver<'_> {
+    /// Returns the value of the variable, or `None` if there isn't one
+    /// or it refers to itself.
+    fn get(&mut self, name: &str) -> Option<String> {
+        if let Some(value) = self.resolved.get(name) {
+            return Some(value.clone());
+        }
+        let (value, expand) = self.raw.get(name)?;
+        if self.resolving.iter().any(|resolving| resolving == name) {
+            return None;
+        }
+
+        self.resolving.push(name.to_string());
+        let value = if *expand {
+            self.expand(value)
+        } else {
+            value.clone()
+        };
+        self.resolving.pop();
+
+        self.resolved.insert(name.to_string(), value.clone());
+        Some(value)
+    }
+
+    /// Expands `$NAME` and `${NAME}` in the value, where `$$` is a
+    /// literal `$`. Names which aren't variables are empty, as belay's
+    /// own environment isn't part of the pipeline.
+    fn expand(&mut self, value: &str) -> String {
+        let mut expanded = String::new();
+        let mut rest = value;
+
+        while let Some(index) = rest.find('$') {
+            expanded.push_str(&rest[..index]);
+            rest = &rest[index + 1..];
+
+            if let Some(after) = rest.strip_prefix('$') {
+                expanded.push('$');
+                rest = after;
+                continue;
+            }
+
+            let (name, after) = match rest.strip_prefix('{') {
+                Some(braced) => match braced.find('}') {
+                    Some(end) => (&braced[..end], &braced[end + 1..]),
+                    None => ("", rest),
+                },
+                None => {
+                    let end = rest
+                        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                        .unwrap_or(rest.len());
+                    (&rest[..end], &rest[end..])
+                }
+            };
+            if name.is_empty() {
+                expanded.push('$');
+                continue;
+            }
+
+            let value = self.get(name).unwrap_or_default();
+            expanded.push_str(&value);
+            rest = after;
+        }
+        expanded.push_str(rest);
+
+        expanded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(vars: &[(&str, &str)]) -> Vec<(String, (String, bool))> {
+        vars.iter()
+            .map(|(k, v)| (k.to_string(), (v.to_string(), true)))
+            .collect()
+    }
+
+    #[test]
+    fn resolve_variables() {
+        let resolved = resolve(vec![
+            set(&[("CI_PROJECT_DIR", "/src"), ("MODE", "debug")]),
+            set(&[
+                ("CARGO_HOME", "$CI_PROJECT_DIR/.cargo"),
+                ("TARGET", "${CARGO_HOME}/target-$MODE"),
+                ("PRICE", "$$5"),
+                ("LOOP", "a$LOOP"),
+            ]),
+            set(&[("MODE", "release")]),
+        ]);
+
+        assert_eq!("/src/.cargo", resolved["CARGO_HOME"]);
+        assert_eq!("/src/.cargo/target-release", resolved["TARGET"]);
+        assert_eq!("$5", resolved["PRICE"]);
+        assert_eq!("a", resolved["LOOP"]);
+    }
+
+    #[test]
+    fn resolve_ignores_environment() {
+        let resolved = resolve(vec![set(&[("GREETING", "hello $HOME${PATH}")])]);
+
+        assert_eq!("hello ", resolved["GREETING"]);
+    }
+
+    #[test]
+    fn predefined_variables() {
+        let project = Project {
+            dir: PathBuf::from("/src/belay"),
+            branch: "Feature/New".to_string(),
+            sha: "0123456789abcdef".to_string(),
+            path: "group/belay".to_string(),
+            user_name: "Someone".to_string(),
+        };
+        let predefined = predefined("test", "check", &project)
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+
+        assert_eq!("belay", predefined["CI_PROJECT_NAME"]);
+        assert_eq!("group", predefined["CI_PROJECT_NAMESPACE"]);
+        assert_eq!("01234567", predefined["CI_COMMIT_SHORT_SHA"]);
+        assert_eq!("feature-new", predefined["CI_COMMIT_REF_SLUG"]);
+        assert_eq!("check", predefined["CI_JOB_STAGE"]);
+    }
+
+    #[test]
+    fn parse_variables() {
+        let variables = serde_yaml::from_str::<HashMap<String, Variable>>(
+            r#"
+RUST_BACKTRACE: 1
+DEPLOY:
+  value: "no"
+  description: Whether to deploy
+RAW:
+  value: $HOME
+  expand: false
+"#,
+        )
+        .unwrap();
+        let resolved = resolve(vec![values(&variables)]);
+
+        assert_eq!("1", resolved["RUST_BACKTRACE"]);
+        assert_eq!("no", resolved["DEPLOY"]);
+        assert_eq!("$HOME", resolved["RAW"]);
+    }
+
+    #[test]
+    fn slug_ref() {
+        assert_eq!("feature-new-ui", slug("Feature/New_UI"));
+    }
+}
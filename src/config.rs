@@ -1,4 +1,4 @@
-use std::fs;
+use std::{collections::HashMap, fs};
 
 use directories::ProjectDirs;
 use serde::{Deserialize, Serialize};
@@ -6,6 +6,37 @@ use serde::{Deserialize, Serialize};
 #[derive(Deserialize, Serialize)]
 pub struct Config {
     pub command_blacklist: Vec<String>,
+    /// The branch pull requests are assumed to target, overriding
+    /// the guess based on the repository.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_branch: Option<String>,
+    /// The values of the `secrets` context, since the secrets of the
+    /// repository aren't available locally.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub secrets: HashMap<String, String>,
+    /// Whether GitHub steps are given the default environment
+    /// variables, such as `CI` and `GITHUB_ACTIONS`. Scripts which
+    /// need to know they aren't really running in CI can disable this.
+    #[serde(default = "enabled")]
+    pub default_env: bool,
+    /// The operating systems of custom `runs-on` labels, such as
+    /// those of self-hosted runners, as `linux`, `macos` or `windows`.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub runner_labels: HashMap<String, String>,
+    /// The docker compatible program, such as `docker` or `podman`,
+    /// which runs jobs with a `container` or `services`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container_runtime: Option<String>,
+    /// Shims for actions, which replace or add to the built-in ones.
+    /// Each maps an action, such as `actions-rs/clippy-check`, to the
+    /// command to run in its place, or to null if it isn't needed
+    /// locally.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub action_shims: HashMap<String, Option<String>>,
+}
+
+fn enabled() -> bool {
+    true
 }
 
 impl Config {
@@ -48,6 +79,12 @@ impl Config {
                 "chown".into(),
                 "rustup component add".into(),
             ],
+            base_branch: None,
+            secrets: HashMap::new(),
+            default_env: true,
+            runner_labels: HashMap::new(),
+            container_runtime: None,
+            action_shims: HashMap::new(),
         }
     }
 }
@@ -0,0 +1,204 @@
+use std::{
+    env::current_dir,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+pub fn find_root() -> Option<PathBuf> {
+    let mut dir = current_dir().ok()?;
+
+    loop {
+        let mut git_dir = dir.clone();
+        git_dir.push(".git");
+
+        if git_dir.exists() {
+            return Some(dir);
+        }
+
+        dir.push("..");
+
+        if !dir.exists() {
+            return None;
+        }
+    }
+}
+
+/// Runs a git command, returning its trimmed output if it succeeded.
+fn output(command: &str) -> Option<String> {
+    #[cfg(not(windows))]
+    let output = Command::new("sh").arg("-c").arg(command).output();
+    #[cfg(windows)]
+    let output = Command::new("cmd").arg("/c").arg(command).output();
+
+    let output = output.expect("failed to run git command");
+
+    if output.status.success() {
+        Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        None
+    }
+}
+
+/// Used to guess if this will turn into a pull request. Has the
+/// limitation that it only works if the upstream repository is
+/// named 'upstream'.
+pub fn has_upstream() -> bool {
+    let remotes = output("git remote").expect("command to get git remotes failed");
+
+    remotes.contains("upstream")
+}
+
+pub fn current_branch() -> String {
+    // We assume that if this command fails it is because no commits exist in this
+    // repository. In that case, use 'master' as a placeholder branch name. This is
+    // unlikely to happen often in real-world usage, but it happens in integration tests.
+    output("git rev-parse --abbrev-ref HEAD").unwrap_or_else(|| "master".into())
+}
+
+/// Returns the hash of the current commit, or `None` if no commits
+/// exist in this repository.
+pub fn head_sha() -> Option<String> {
+    output("git rev-parse HEAD")
+}
+
+/// Returns the repository name in the `owner/name` form GitHub uses,
+/// based on the url of the `origin` remote.
+pub fn repository() -> Option<String> {
+    let url = output("git remote get-url origin")?;
+    let path = url.trim_end_matches('/').trim_end_matches(".git");
+
+    // Handles both `https://github.com/owner/name` and `git@github.com:owner/name`
+    let mut parts = path.rsplit(['/', ':']);
+    let name = parts.next()?;
+    let owner = parts.next()?;
+
+    Some(format!("{}/{}", owner, name))
+}
+
+pub fn user_name() -> Option<String> {
+    output("git config user.name")
+}
+
+/// Returns the files in the directory, relative to it, which are
+/// tracked or untracked but not ignored.
+pub fn files(dir: &Path) -> Option<Vec<String>> {
+    let output = Command::new("git")
+        .args([
+            "ls-files",
+            "--cached",
+            "--others",
+            "--exclude-standard",
+            "-z",
+        ])
+        .current_dir(dir)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    let mut files = String::from_utf8_lossy(&output.stdout)
+        .split('\0')
+        .filter(|file| !file.is_empty())
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    // Files with merge conflicts are listed once per stage
+    files.dedup();
+
+    Some(files)
+}
+
+/// Returns the files which are staged to be committed.
+pub fn staged_files() -> Option<Vec<String>> {
+    output("git diff --cached --name-only").map(|files| lines(&files))
+}
+
+/// Returns the files changed by the commits being pushed, given the
+/// refs git passes to the pre-push hook on stdin, one per line as
+/// `<local ref> <local sha> <remote ref> <remote sha>`.
+pub fn pushed_files(refs: &str) -> Option<Vec<String>> {
+    let mut files = vec![];
+
+    for line in refs.lines() {
+        let (local_sha, remote_sha) = match line.split_whitespace().collect::<Vec<_>>()[..] {
+            [_, local_sha, _, remote_sha] => (local_sha, remote_sha),
+            _ => continue,
+        };
+        let is_zero = |sha: &str| sha.chars().all(|c| c == '0');
+
+        // Nothing is being committed when a remote branch is deleted
+        if is_zero(local_sha) {
+            continue;
+        }
+        // For a new remote branch, the commits being pushed are those
+        // which aren't already on any remote branch.
+        let commits = if is_zero(remote_sha) {
+            format!("{} --not --remotes", local_sha)
+        } else {
+            format!("{}..{}", remote_sha, local_sha)
+        };
+
+        let changed = output(&format!("git log --format= --name-only {}", commits))?;
+        files.extend(lines(&changed));
+    }
+
+    files.sort();
+    files.dedup();
+
+    Some(files)
+}
+
+/// Returns the files changed on this branch since it diverged from
+/// the base branch, including uncommitted and untracked files.
+pub fn branch_files(base_ref: &str) -> Option<Vec<String>> {
+    let merge_base = output(&format!("git merge-base HEAD {}", base_ref))?;
+
+    let mut files = lines(&output(&format!("git diff --name-only {}", merge_base))?);
+    files.extend(lines(&output("git ls-files --others --exclude-standard")?));
+    files.sort();
+    files.dedup();
+
+    Some(files)
+}
+
+/// Returns the remote branch the current branch is probably based on,
+/// and so the branch a pull request from it would target. This is its
+/// upstream tracking branch, unless that is just the same branch on
+/// the remote, or else the default branch of `origin`.
+pub fn base_ref() -> Option<String> {
+    let current_branch = current_branch();
+
+    upstream_ref()
+        .filter(|upstream| branch_name(upstream) != current_branch)
+        .or_else(|| output("git rev-parse --abbrev-ref origin/HEAD"))
+}
+
+/// Returns whether the current branch has been pushed, based on
+/// whether it has an upstream tracking branch.
+pub fn has_tracking_branch() -> bool {
+    upstream_ref().is_some()
+}
+
+fn upstream_ref() -> Option<String> {
+    output("git rev-parse --abbrev-ref --symbolic-full-name @{upstream}")
+}
+
+/// Returns the name of a branch without its remote, so `origin/main`
+/// becomes `main`.
+pub fn branch_name(git_ref: &str) -> String {
+    let remotes = output("git remote").unwrap_or_default();
+
+    lines(&remotes)
+        .iter()
+        .find_map(|remote| git_ref.strip_prefix(&format!("{}/", remote)))
+        .unwrap_or(git_ref)
+        .to_string()
+}
+
+fn lines(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| line.to_string())
+        .collect()
+}
@@ -1,11 +1,8 @@
 use std::{
-    collections::HashSet,
     convert::TryFrom,
-    env::current_dir,
     fs::{read_dir, read_to_string, File},
-    io::Write,
+    io::{self, Read, Write},
     path::{Path, PathBuf},
-    process::Command,
 };
 use structopt::StructOpt;
 
@@ -13,18 +10,23 @@ use structopt::StructOpt;
 use std::{fs::Permissions, os::unix::fs::PermissionsExt};
 
 mod args;
-use args::{Args, Subcommand};
+use args::{Args, HookType, Subcommand};
 
 mod ci;
-use ci::{github, gitlab, Task, TaskList, Trigger};
+use ci::{github, gitlab, JobStatus, TaskList, Trigger};
 
 mod config;
 use config::Config;
 
+mod git;
+
+mod runner;
+use runner::Runner;
+
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
 fn main() -> Result<()> {
-    let root_dir = find_git_root().ok_or("Failed to find git root")?;
+    let root_dir = git::find_root().ok_or("Failed to find git root")?;
 
     let args = Args::from_args();
 
@@ -40,54 +42,85 @@ fn main() -> Result<()> {
         #[cfg(not(windows))]
         file.set_permissions(Permissions::from_mode(0o755))?;
 
-        file.write_all(b"#!/bin/sh\nbelay")?;
+        file.write_all(format!("#!/bin/sh\nbelay --from-hook {}", hook_filename).as_bytes())?;
 
         println!("Created hook `.git/hooks/{}`", hook_filename);
 
         return Ok(());
     }
 
+    let config = Config::read();
+
     let ci_configs: Vec<Box<dyn TaskList>> =
-        match (handle_github(&root_dir), handle_gitlab(&root_dir)) {
+        match (handle_github(&root_dir, &config), handle_gitlab(&root_dir)) {
             (Ok(configs), _) => configs
                 .into_iter()
                 .map(|c| Box::new(c) as Box<dyn TaskList>)
                 .collect(),
-            (_, Ok(config)) => vec![Box::new(config)],
+            (_, Ok(config)) => {
+                config.check()?;
+                vec![Box::new(config)]
+            }
             _ => return Err("Unable to find CI configuration".into()),
         };
 
-    let mut completed_commands = HashSet::new();
-    for ci_config in ci_configs {
-        for task in ci_config.tasks(Config::read(), get_triggers()) {
-            let Task { name, command, .. } = task;
+    let base_ref = config.base_branch.clone().or_else(git::base_ref);
 
-            // we want to de-duplicate commands across CI configurations
-            if completed_commands.contains(&command) {
-                continue;
-            }
-
-            let task_name = name.unwrap_or_else(|| command.clone());
-            println!("Checking '{}':", task_name);
-
-            #[cfg(not(windows))]
-            let status = Command::new("sh").arg("-c").arg(&command).status()?;
-            #[cfg(windows)]
-            let status = Command::new("cmd").arg("/c").arg(&command).status()?;
+    let changed_files = match args.from_hook {
+        Some(HookType::Commit) => git::staged_files(),
+        Some(HookType::Push) => {
+            let mut refs = String::new();
+            io::stdin().read_to_string(&mut refs)?;
+            git::pushed_files(&refs)
+        }
+        None => base_ref.as_deref().and_then(git::branch_files),
+    };
+    let base_branch = base_ref.as_deref().map(git::branch_name);
+
+    let triggers = match args.event.as_deref() {
+        None => get_triggers(base_branch, changed_files),
+        Some("push") => vec![push_trigger(changed_files)],
+        Some("pull_request") => vec![pull_request_trigger(base_branch, changed_files)],
+        Some(event) => vec![Trigger::Event {
+            name: event.to_string(),
+            inputs: args.inputs.into_iter().collect(),
+        }],
+    };
 
-            if status.success() {
-                println!("Success!");
-            } else {
-                return Err("Failed".into());
-            }
-            completed_commands.insert(command);
+    let mut runner = Runner::new(
+        root_dir,
+        runner::Options {
+            collapse_groups: args.collapse_groups,
+            container_runtime: args
+                .container_runtime
+                .clone()
+                .or_else(|| config.container_runtime.clone()),
+            default_env: config.default_env,
+            ignore_runs_on: args.ignore_runs_on,
+            runner_labels: config.runner_labels.clone(),
+        },
+    );
+    let mut status = JobStatus::Success;
+    for ci_config in ci_configs {
+        let jobs = ci_config.jobs(&config, &triggers, &args.matrix)?;
+        if runner.run_jobs(jobs)? == JobStatus::Failure {
+            status = JobStatus::Failure;
         }
     }
+    runner.print_summaries();
+    runner.print_annotations();
+    runner.print_combinations();
+
+    if status == JobStatus::Failure {
+        return Err("Failed".into());
+    }
 
     Ok(())
 }
 
-fn handle_github(root_dir: &Path) -> Result<Vec<github::CiConfig>> {
+fn handle_github(root_dir: &Path, config: &Config) -> Result<Vec<github::CiConfig>> {
+    let shims = github::shim::Shims::new(&config.action_shims);
+
     let github_workflows_dir = {
         let mut gh = root_dir.to_path_buf();
         gh.push(".github");
@@ -108,7 +141,12 @@ fn handle_github(root_dir: &Path) -> Result<Vec<github::CiConfig>> {
     let configs = paths
         .into_iter()
         .map(|path| -> Result<github::CiConfig> {
-            Ok(github::CiConfig::try_from(read_to_string(path)?.as_str())?)
+            let mut config = github::CiConfig::try_from(read_to_string(path)?.as_str())?;
+            config.expand_local_actions(root_dir)?;
+            config.expand_reusable_workflows(root_dir)?;
+            config.apply_action_shims(&shims)?;
+
+            Ok(config)
         })
         .collect::<Result<Vec<github::CiConfig>>>()?;
 
@@ -132,75 +170,43 @@ fn handle_gitlab(root_dir: &Path) -> Result<gitlab::CiConfig> {
     )?)?)
 }
 
-fn find_git_root() -> Option<PathBuf> {
-    let mut dir = current_dir().ok()?;
-
-    loop {
-        let mut git_dir = dir.clone();
-        git_dir.push(".git");
-
-        if git_dir.exists() {
-            return Some(dir);
-        }
-
-        dir.push("..");
-
-        if !dir.exists() {
-            return None;
-        }
-    }
-}
-
 /// Get the best estimate of the triggers for this CI run.
 ///
 /// We can't know for sure if this will turn into a pull
 /// request, but we assume if there is an upstream remote
 /// that it will.
-fn get_triggers() -> Vec<Trigger> {
-    let mut triggers = vec![Trigger::Push {
-        branch: current_branch(),
-    }];
+fn get_triggers(base_branch: Option<String>, changed_files: Option<Vec<String>>) -> Vec<Trigger> {
+    let mut triggers = vec![push_trigger(changed_files.clone())];
 
-    if has_upstream() {
-        triggers.push(Trigger::PullRequest);
+    if git::has_upstream() {
+        triggers.push(pull_request_trigger(base_branch, changed_files));
     }
 
     triggers
 }
 
-/// Used to guess if this will turn into a pull request. Has the
-/// limitation that it only works if the upstream repository is
-/// named 'upstream'.
-fn has_upstream() -> bool {
-    let command = "git remote";
-    #[cfg(not(windows))]
-    let output = Command::new("sh").arg("-c").arg(&command).output();
-    #[cfg(windows)]
-    let output = Command::new("cmd").arg("/c").arg(&command).output();
-
-    let output = output.expect("failed to run git command");
-
-    assert!(output.status.success(), "command to get git remotes failed");
-
-    let remotes = String::from_utf8_lossy(&output.stdout).into_owned();
-    remotes.contains("upstream")
+fn push_trigger(changed_files: Option<Vec<String>>) -> Trigger {
+    Trigger::Push {
+        branch: git::current_branch(),
+        changed_files,
+    }
 }
 
-fn current_branch() -> String {
-    let command = "git rev-parse --abbrev-ref HEAD";
-    #[cfg(not(windows))]
-    let output = Command::new("sh").arg("-c").arg(&command).output();
-    #[cfg(windows)]
-    let output = Command::new("cmd").arg("/c").arg(&command).output();
-
-    let output = output.expect("failed to run git command");
-
-    if output.status.success() {
-        String::from_utf8_lossy(&output.stdout).into_owned()
+fn pull_request_trigger(
+    base_branch: Option<String>,
+    changed_files: Option<Vec<String>>,
+) -> Trigger {
+    // A branch which was already pushed probably has an open pull
+    // request, which pushing to again would synchronize.
+    let action = if git::has_tracking_branch() {
+        "synchronize"
     } else {
-        // We assume that if this command fails it is because no commits exist in this
-        // repository. In that case, use 'master' as a placeholder branch name. This is
-        // unlikely to happen often in real-world usage, but it happens in integration tests.
-        "master".into()
+        "opened"
+    };
+
+    Trigger::PullRequest {
+        base_branch,
+        action: action.to_string(),
+        changed_files,
     }
 }
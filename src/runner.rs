@@ -0,0 +1,826 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    env::temp_dir,
+    ffi::OsString,
+    fs::{self, remove_file},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::{self, Child, Command},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+#[cfg(not(windows))]
+use std::os::unix::process::CommandExt;
+
+use commands::WorkflowCommands;
+use container::{ContainerRuntime, JobContainers};
+use environment::JobEnvironment;
+
+use crate::{
+    ci::{Job, JobStatus, Shell, Task, TaskKey},
+    Result,
+};
+
+mod commands;
+mod container;
+mod environment;
+
+/// Runner executes jobs on the local machine.
+pub struct Runner {
+    root_dir: PathBuf,
+    /// The tasks which have already succeeded, since we want to
+    /// de-duplicate commands across CI configurations.
+    completed_tasks: HashSet<TaskKey>,
+    /// The step summaries written by each job, to show once every
+    /// job has run.
+    summaries: Vec<(String, String)>,
+    /// The id, name and result of each matrix combination which has
+    /// been run, to summarize once every job has run.
+    combinations: Vec<(String, String, JobStatus)>,
+    /// Carries out the workflow commands in the output of GitHub steps.
+    commands: WorkflowCommands,
+    options: Options,
+    /// Where the output of jobs is written.
+    out: Output,
+    /// Whether another combination of the matrix being run has failed,
+    /// if this runner runs one of several combinations at once.
+    fail_fast: Option<Arc<FailFast>>,
+}
+
+/// Output is where a runner writes the output of jobs. Combinations
+/// which run at the same time buffer theirs, so it can be shown in
+/// order once they are done.
+enum Output {
+    Stdout,
+    Buffer(Vec<u8>),
+}
+
+impl Output {
+    /// Takes the output buffered so far.
+    fn take(&mut self) -> Vec<u8> {
+        match self {
+            Output::Stdout => vec![],
+            Output::Buffer(buffer) => std::mem::take(buffer),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Stdout => io::stdout().write(buf),
+            Output::Buffer(buffer) => buffer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Stdout => io::stdout().flush(),
+            Output::Buffer(_) => Ok(()),
+        }
+    }
+}
+
+/// FailFast records the first combination of a matrix to fail, so the
+/// combinations running alongside it can be cancelled.
+#[derive(Default)]
+struct FailFast {
+    failed: Mutex<Option<String>>,
+}
+
+impl FailFast {
+    fn fail(&self, name: &str) {
+        let mut failed = self.failed.lock().expect("lock should not be poisoned");
+        failed.get_or_insert_with(|| name.to_string());
+    }
+
+    /// Returns the name of the combination which failed, if one has.
+    fn failed(&self) -> Option<String> {
+        self.failed
+            .lock()
+            .expect("lock should not be poisoned")
+            .clone()
+    }
+}
+
+/// Options changes how the runner shows the output of tasks.
+#[derive(Clone, Default)]
+pub struct Options {
+    /// Whether the output within `::group::` workflow commands is
+    /// hidden.
+    pub collapse_groups: bool,
+    /// The docker compatible program which runs jobs with a
+    /// `container` or `services`. Such jobs run on this machine if
+    /// this isn't set.
+    pub container_runtime: Option<String>,
+    /// Whether GitHub steps are given the default variables GitHub
+    /// Actions sets, such as `CI` and `GITHUB_SHA`.
+    pub default_env: bool,
+    /// Whether jobs are run even if their `runs-on` labels are for a
+    /// different operating system.
+    pub ignore_runs_on: bool,
+    /// The operating systems of custom `runs-on` labels, such as
+    /// those of self-hosted runners.
+    pub runner_labels: HashMap<String, String>,
+}
+
+impl Runner {
+    pub fn new(root_dir: PathBuf, options: Options) -> Self {
+        Self {
+            root_dir,
+            completed_tasks: HashSet::new(),
+            summaries: vec![],
+            combinations: vec![],
+            commands: WorkflowCommands::new(options.collapse_groups),
+            options,
+            out: Output::Stdout,
+            fail_fast: None,
+        }
+    }
+
+    /// Returns a runner for a combination which runs alongside others,
+    /// buffering its output until it is joined back.
+    fn fork(&self, fail_fast: &Arc<FailFast>) -> Self {
+        Self {
+            root_dir: self.root_dir.clone(),
+            completed_tasks: self.completed_tasks.clone(),
+            summaries: vec![],
+            combinations: vec![],
+            commands: self.commands.fork(),
+            options: self.options.clone(),
+            out: Output::Buffer(vec![]),
+            fail_fast: Some(fail_fast.clone()),
+        }
+    }
+
+    /// Adds the completed tasks, summaries and annotations of a runner
+    /// from `fork`.
+    fn join(&mut self, other: Self) {
+        self.completed_tasks.extend(other.completed_tasks);
+        self.summaries.extend(other.summaries);
+        self.commands.join(other.commands);
+    }
+
+    /// Prints the annotations created by workflow commands, such as
+    /// `::error::`, in the jobs which have run.
+    pub fn print_annotations(&self) {
+        let annotations = self.commands.annotations();
+        if annotations.is_empty() {
+            return;
+        }
+
+        println!("Annotations:");
+        for annotation in annotations {
+            println!("{}", annotation);
+        }
+    }
+
+    /// Prints the step summaries written by the jobs which have run.
+    pub fn print_summaries(&self) {
+        for (job, summary) in &self.summaries {
+            println!("Summary of '{}':", job);
+            print!("{}", summary);
+            if !summary.ends_with('\n') {
+                println!();
+            }
+        }
+    }
+
+    /// Prints the result of each matrix combination which has run,
+    /// under the id of its job.
+    pub fn print_combinations(&self) {
+        let mut current = None;
+        for (id, name, status) in &self.combinations {
+            if current != Some(id) {
+                println!("Results of '{}':", id);
+                current = Some(id);
+            }
+            println!("  {}: {}", name, status);
+        }
+    }
+
+    /// Runs the jobs from a CI configuration in order, returning
+    /// `JobStatus::Failure` if any of them failed.
+    pub fn run_jobs(&mut self, jobs: Vec<Job>) -> Result<JobStatus> {
+        let mut results = HashMap::new();
+        let mut status = JobStatus::Success;
+        // The combination which failed, for matrix jobs whose
+        // remaining combinations are cancelled.
+        let mut failed_fast: HashMap<String, String> = HashMap::new();
+
+        let mut stage = None;
+
+        let mut jobs = jobs.into_iter().peekable();
+        while let Some(job) = jobs.next() {
+            if job.stage.is_some() && job.stage != stage {
+                stage = job.stage.clone();
+                println!("Stage '{}':", stage.as_deref().unwrap_or_default());
+            }
+
+            // The combinations of a matrix with a `max-parallel` above
+            // one run together, up to that many at once
+            let limit = job.max_parallel();
+            let mut batch = vec![job];
+            if limit > 1 {
+                while let Some(job) = jobs.next_if(|job| job.id == batch[0].id) {
+                    batch.push(job);
+                }
+            }
+            let statuses = if batch.len() > 1 {
+                self.run_combinations(&mut batch, limit, &results)?
+            } else {
+                let job = &mut batch[0];
+                vec![match failed_fast.get(&job.id) {
+                    Some(failed) => {
+                        println!("Cancelling '{}', since '{}' failed", job.name, failed);
+                        JobStatus::Cancelled
+                    }
+                    None => self.run_job(job, &results)?,
+                }]
+            };
+
+            for (job, job_status) in batch.iter().zip(statuses) {
+                let id = job.id.clone();
+                let failed = job_status == JobStatus::Failure || job_status == JobStatus::Cancelled;
+                if failed {
+                    status = JobStatus::Failure;
+                }
+                if job.is_matrix() {
+                    // As on GitHub, a combination cancelled by its timeout
+                    // counts as having failed
+                    if failed && job.fails_fast() {
+                        failed_fast.entry(id.clone()).or_insert(job.name.clone());
+                    }
+                    self.combinations
+                        .push((id.clone(), job.name.clone(), job_status));
+                }
+
+                // A job expanded from a matrix fails if any combination
+                // failed, and is only skipped if every combination was.
+                let result = results.entry(id).or_insert(job_status);
+                if failed || *result == JobStatus::Skipped {
+                    *result = job_status;
+                }
+            }
+        }
+
+        Ok(status)
+    }
+
+    /// Runs the combinations of a matrix, up to `limit` of them at once,
+    /// returning the status of each. Their output is shown in order, as
+    /// each combination and those before it are done.
+    fn run_combinations(
+        &mut self,
+        jobs: &mut [Job],
+        limit: usize,
+        results: &HashMap<String, JobStatus>,
+    ) -> Result<Vec<JobStatus>> {
+        let count = jobs.len();
+        let fail_fast = Arc::new(FailFast::default());
+        let mut runners = jobs
+            .iter()
+            .map(|_| self.fork(&fail_fast))
+            .collect::<Vec<_>>();
+        let queue = Mutex::new(
+            jobs.iter_mut()
+                .zip(runners.iter_mut())
+                .enumerate()
+                .collect::<VecDeque<_>>(),
+        );
+
+        // Errors are passed back as strings, since they can't be sent
+        // between threads
+        let mut statuses: Vec<Option<std::result::Result<JobStatus, String>>> = vec![None; count];
+        let (sender, receiver) = mpsc::channel();
+        thread::scope(|scope| {
+            for _ in 0..limit.min(count) {
+                let sender = sender.clone();
+                let (queue, fail_fast) = (&queue, &fail_fast);
+                scope.spawn(move || loop {
+                    let next = queue
+                        .lock()
+                        .expect("lock should not be poisoned")
+                        .pop_front();
+                    let (index, (job, runner)) = match next {
+                        Some(next) => next,
+                        None => break,
+                    };
+
+                    let status = match fail_fast.failed() {
+                        Some(failed) => writeln!(
+                            runner.out,
+                            "Cancelling '{}', since '{}' failed",
+                            job.name, failed
+                        )
+                        .map(|_| JobStatus::Cancelled)
+                        .map_err(|e| e.to_string()),
+                        None => runner.run_job(job, results).map_err(|e| e.to_string()),
+                    };
+                    let failed = match &status {
+                        Ok(status) => {
+                            *status == JobStatus::Failure || *status == JobStatus::Cancelled
+                        }
+                        Err(_) => true,
+                    };
+                    if failed && job.fails_fast() {
+                        fail_fast.fail(&job.name);
+                    }
+
+                    let _ = sender.send((index, runner.out.take(), status));
+                });
+            }
+            drop(sender);
+
+            let mut outputs = vec![None; count];
+            let mut shown = 0;
+            for (index, output, status) in receiver {
+                outputs[index] = Some(output);
+                statuses[index] = Some(status);
+                while let Some(Some(output)) = outputs.get(shown) {
+                    io::stdout().write_all(output)?;
+                    shown += 1;
+                }
+            }
+
+            io::Result::Ok(())
+        })?;
+
+        drop(queue);
+        for runner in runners {
+            self.join(runner);
+        }
+
+        statuses
+            .into_iter()
+            .map(|status| {
+                status
+                    .expect("every combination should have run")
+                    .map_err(|e| e.into())
+            })
+            .collect()
+    }
+
+    /// Runs the tasks in a job, returning the status of the job.
+    fn run_job(
+        &mut self,
+        job: &mut Job,
+        results: &HashMap<String, JobStatus>,
+    ) -> Result<JobStatus> {
+        if !job.should_run(&self.root_dir, results)? {
+            writeln!(self.out, "Skipping '{}'", job.name)?;
+            return Ok(JobStatus::Skipped);
+        }
+        if !self.options.ignore_runs_on {
+            if let Some(label) = job.unsupported_runner(&self.options.runner_labels) {
+                writeln!(
+                    self.out,
+                    "Skipping '{}', since it runs on '{}'",
+                    job.name, label
+                )?;
+                return Ok(JobStatus::Skipped);
+            }
+        }
+
+        let continue_on_error = job.continues_on_error();
+        let deadline = job
+            .timeout()
+            .and_then(|timeout| Instant::now().checked_add(timeout));
+
+        let mut environment = if job.emulates_github() {
+            Some(JobEnvironment::new(
+                job,
+                &self.root_dir,
+                self.options.default_env,
+            )?)
+        } else {
+            None
+        };
+        let containers = match &self.options.container_runtime {
+            Some(runtime) if job.container().is_some() || !job.services().is_empty() => {
+                match JobContainers::start(ContainerRuntime::new(runtime), job, &self.root_dir) {
+                    Ok(containers) => Some(containers),
+                    Err(e) => {
+                        writeln!(
+                            self.out,
+                            "Unable to start the containers of '{}': {}",
+                            job.name, e
+                        )?;
+                        return Ok(JobStatus::Failure);
+                    }
+                }
+            }
+            _ => None,
+        };
+
+        // The combinations of a matrix which run at once share whether
+        // one of them failed
+        let fail_fast = self.fail_fast.clone();
+        let mut status = JobStatus::Success;
+        for mut task in std::mem::take(&mut job.tasks) {
+            self.cancel_if_failed_fast(job, &mut status)?;
+            if let Some(environment) = &mut environment {
+                let skipped = environment.enter_actions(
+                    &task,
+                    &self.root_dir,
+                    job.trigger.as_ref(),
+                    status,
+                )?;
+                for name in skipped {
+                    writeln!(self.out, "Skipping '{}'", name)?;
+                }
+                if environment.in_skipped_action() {
+                    environment.finish_step(&task, JobStatus::Skipped)?;
+                    continue;
+                }
+                environment.prepare(&mut task);
+            }
+            task.evaluate_expressions(&self.root_dir, job.trigger.as_ref(), status)?;
+
+            let key = task.key();
+            if self.completed_tasks.contains(&key) {
+                if let Some(environment) = &mut environment {
+                    environment.finish_step(&task, JobStatus::Skipped)?;
+                }
+                continue;
+            }
+
+            let task_name = task.name.clone().unwrap_or_else(|| task.command.clone());
+            let skip_message = if !task.should_run(status)? {
+                Some(format!("Skipping '{}'", task_name))
+            } else {
+                task.skip_reason
+                    .as_ref()
+                    .map(|reason| format!("Skipping '{}', since {}", task_name, reason))
+            };
+            if let Some(message) = skip_message {
+                writeln!(self.out, "{}", message)?;
+                if let Some(environment) = &mut environment {
+                    environment.finish_step(&task, JobStatus::Skipped)?;
+                }
+                continue;
+            }
+
+            // Until the job is cancelled, its timeout also limits each of its tasks.
+            let timeout = match deadline {
+                Some(deadline) if status != JobStatus::Cancelled => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    Some(
+                        task.timeout()
+                            .map_or(remaining, |timeout| timeout.min(remaining)),
+                    )
+                }
+                _ => task.timeout(),
+            };
+            let (env, paths) = match &environment {
+                Some(environment) => (environment.command_env(&task)?, environment.path()),
+                None => (vec![], &[][..]),
+            };
+
+            writeln!(self.out, "Checking '{}':", task_name)?;
+            let success = self.run_task(
+                &task,
+                &env,
+                timeout,
+                fail_fast
+                    .as_deref()
+                    .filter(|_| status != JobStatus::Cancelled),
+                environment.is_some(),
+                containers.as_ref().map(|containers| (containers, paths)),
+            )?;
+            if let Some(environment) = &mut environment {
+                let outcome = if success {
+                    JobStatus::Success
+                } else {
+                    JobStatus::Failure
+                };
+                environment.finish_step(&task, outcome)?;
+            }
+
+            if success {
+                writeln!(self.out, "Success!")?;
+                self.completed_tasks.insert(key);
+            } else if self.cancel_if_failed_fast(job, &mut status)? {
+                // The task was killed, since another combination failed
+            } else if deadline.is_some_and(|deadline| Instant::now() >= deadline)
+                && status != JobStatus::Cancelled
+            {
+                writeln!(
+                    self.out,
+                    "Cancelling '{}', since it exceeded its timeout",
+                    job.name
+                )?;
+                status = JobStatus::Cancelled;
+            } else if task.continues_on_error() {
+                writeln!(
+                    self.out,
+                    "Warning: '{}' failed, continuing since it has continue-on-error set",
+                    task_name
+                )?;
+            } else {
+                status = JobStatus::Failure;
+            }
+        }
+
+        if let Some(mut environment) = environment {
+            environment.leave_actions()?;
+            if !environment.summary().is_empty() {
+                self.summaries
+                    .push((job.name.clone(), environment.summary().to_string()));
+            }
+        }
+
+        if status != JobStatus::Success && continue_on_error {
+            writeln!(
+                self.out,
+                "Warning: '{}' failed, continuing since it has continue-on-error set",
+                job.name
+            )?;
+            return Ok(JobStatus::Success);
+        }
+
+        Ok(status)
+    }
+
+    /// Cancels the job once another combination of its matrix which
+    /// runs at the same time has failed, as on GitHub. Returns whether
+    /// it was cancelled just now.
+    fn cancel_if_failed_fast(&mut self, job: &Job, status: &mut JobStatus) -> io::Result<bool> {
+        if *status == JobStatus::Cancelled {
+            return Ok(false);
+        }
+        let failed = match self
+            .fail_fast
+            .as_ref()
+            .and_then(|fail_fast| fail_fast.failed())
+        {
+            Some(failed) => failed,
+            None => return Ok(false),
+        };
+
+        writeln!(
+            self.out,
+            "Cancelling '{}', since '{}' failed",
+            job.name, failed
+        )?;
+        *status = JobStatus::Cancelled;
+
+        Ok(true)
+    }
+
+    /// Runs a single task with the extra environment variables,
+    /// returning whether it succeeded. The task is killed if it runs
+    /// for longer than the timeout. Workflow commands in its output are
+    /// carried out if `parse_commands` is set. The task runs in the
+    /// job's container, if it has one, with the directories the job's
+    /// steps added to the `PATH`. It is also killed once another
+    /// combination fails, if `cancel` is given.
+    fn run_task(
+        &mut self,
+        task: &Task,
+        env: &[(String, OsString)],
+        timeout: Option<Duration>,
+        cancel: Option<&FailFast>,
+        parse_commands: bool,
+        containers: Option<(&JobContainers, &[String])>,
+    ) -> Result<bool> {
+        let mut current_dir = self.root_dir.clone();
+        if let Some(working_directory) = &task.working_directory {
+            current_dir.push(working_directory);
+        }
+
+        // The default shell depends on what the job's container has
+        // installed, rather than this machine
+        let container_shell = containers
+            .and_then(|(containers, _)| containers.default_shell())
+            .filter(|_| task.default_shell);
+        let (mut process, script) = match container_shell.or(task.shell.as_ref()) {
+            Some(shell) => {
+                let script = write_script(&task.command, shell.extension)?;
+                (script_command(shell, &script), Some(script))
+            }
+            None => (default_shell_command(&task.command), None),
+        };
+        process
+            .envs(&task.env)
+            .envs(env.iter().map(|(k, v)| (k, v)))
+            .current_dir(current_dir);
+        // The containers of the job, if the task runs in one of them
+        let mut in_container = None;
+        if let Some((containers, paths)) = containers {
+            if let Some(exec) = containers.exec(&process, paths) {
+                process = exec;
+                in_container = Some(containers);
+            }
+        }
+
+        let commands = if parse_commands {
+            Some(&mut self.commands)
+        } else {
+            None
+        };
+        let status = run_process(process, timeout, cancel, commands, &mut self.out);
+
+        if let Some(script) = script {
+            remove_file(script)?;
+        }
+
+        match status? {
+            Some(success) => Ok(success),
+            None => {
+                if let Some(containers) = in_container {
+                    containers.kill_step();
+                }
+                // Cancellation is reported along with the job's
+                if cancel.is_none_or(|cancel| cancel.failed().is_none()) {
+                    writeln!(self.out, "Timed out")?;
+                }
+                Ok(false)
+            }
+        }
+    }
+}
+
+/// Runs the command, returning whether it succeeded, or `None` if it
+/// was killed for running longer than the timeout, or for another
+/// combination failing. A command which can't be started counts as
+/// having failed. The output of the command is passed through the
+/// workflow commands to `out`, if given.
+fn run_process(
+    mut process: Command,
+    timeout: Option<Duration>,
+    cancel: Option<&FailFast>,
+    commands: Option<&mut WorkflowCommands>,
+    out: &mut Output,
+) -> io::Result<Option<bool>> {
+    // Run the command in its own process group, so anything it starts
+    // can be killed along with it. This is only done when it may be
+    // killed, since it stops the command from receiving Ctrl-C.
+    if timeout.is_some() || cancel.is_some() {
+        #[cfg(not(windows))]
+        process.process_group(0);
+    }
+    // Workflow commands may be written to either stream, so both are
+    // read from the same pipe, keeping the order they were written in
+    let output = match commands {
+        Some(_) => {
+            let (reader, writer) = io::pipe()?;
+            process.stdout(writer.try_clone()?).stderr(writer);
+            Some(reader)
+        }
+        None => None,
+    };
+
+    // As on GitHub, a shell which isn't installed fails the task, rather
+    // than the whole run
+    let mut child = match process.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            writeln!(
+                out,
+                "Failed to run '{}': {}",
+                process.get_program().to_string_lossy(),
+                e
+            )?;
+            return Ok(Some(false));
+        }
+    };
+    // The command holds the pipe open until it is dropped, which would
+    // stop the output from ending when the process exits
+    drop(process);
+
+    thread::scope(|scope| {
+        let reader = commands
+            .zip(output)
+            .map(|(commands, output)| scope.spawn(move || commands.process(output, out)));
+
+        let status = wait_with_timeout(&mut child, timeout, cancel);
+        if let Some(reader) = reader {
+            reader.join().expect("output reader should not panic")?;
+        }
+
+        status
+    })
+}
+
+/// Waits for the child to exit, returning `None` if it was killed for
+/// running longer than the timeout, or for another combination failing.
+fn wait_with_timeout(
+    child: &mut Child,
+    timeout: Option<Duration>,
+    cancel: Option<&FailFast>,
+) -> io::Result<Option<bool>> {
+    // A timeout too long to represent never expires
+    let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+    if deadline.is_none() && cancel.is_none() {
+        return child.wait().map(|status| Some(status.success()));
+    }
+
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status.success()));
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline)
+            || cancel.is_some_and(|cancel| cancel.failed().is_some())
+        {
+            kill_process_tree(child)?;
+            return Ok(None);
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Kills the child along with any processes it started.
+fn kill_process_tree(child: &mut Child) -> io::Result<()> {
+    #[cfg(not(windows))]
+    let mut kill = {
+        let mut kill = Command::new("kill");
+        kill.arg("-KILL").arg("--").arg(format!("-{}", child.id()));
+        kill
+    };
+    #[cfg(windows)]
+    let mut kill = {
+        let mut kill = Command::new("taskkill");
+        kill.arg("/T")
+            .arg("/F")
+            .arg("/PID")
+            .arg(child.id().to_string());
+        kill
+    };
+
+    if !kill.status()?.success() {
+        child.kill()?;
+    }
+    child.wait()?;
+
+    Ok(())
+}
+
+/// Creates a command which runs the given command string using the
+/// platform default shell.
+fn default_shell_command(command: &str) -> Command {
+    #[cfg(not(windows))]
+    let mut process = Command::new("sh");
+    #[cfg(not(windows))]
+    process.arg("-c");
+    #[cfg(windows)]
+    let mut process = Command::new("cmd");
+    #[cfg(windows)]
+    process.arg("/c");
+
+    process.arg(command);
+
+    process
+}
+
+/// Creates a command which runs the given script file using the
+/// shell, by substituting the path to the script for `{0}`.
+fn script_command(shell: &Shell, script: &Path) -> Command {
+    let script = script.to_string_lossy();
+    let mut args = shell.args.iter().map(|arg| arg.replace("{0}", &script));
+
+    let mut process = Command::new(args.next().expect("shell should have a program"));
+    process.args(args);
+
+    process
+}
+
+/// Writes a command to a script file in the temp directory, as
+/// GitHub Actions does before passing it to the shell.
+fn write_script(command: &str, extension: &str) -> Result<PathBuf> {
+    static SCRIPT_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    let mut path = temp_dir();
+    path.push(format!(
+        "belay-{}-{}{}",
+        process::id(),
+        SCRIPT_COUNT.fetch_add(1, Ordering::SeqCst),
+        extension
+    ));
+    fs::write(&path, command)?;
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(not(windows))]
+    fn wait_with_long_timeout() {
+        let mut child = Command::new("true").spawn().unwrap();
+
+        // The deadline would be too far away to represent
+        assert_eq!(
+            Some(true),
+            wait_with_timeout(&mut child, Some(Duration::MAX), None).unwrap()
+        );
+    }
+}
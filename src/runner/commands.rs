@@ -0,0 +1,333 @@
+//! Workflow commands, which a GitHub step runs by printing lines such
+//! as `::error file=src/lib.rs,line=10::message` to its output.
+
+use std::{
+    fmt::{self, Display},
+    io::{self, BufRead, BufReader, Read, Write},
+};
+
+/// The text masked values are replaced with.
+const MASK: &str = "***";
+
+/// Annotation is a message attached to a file, which GitHub would show
+/// alongside the code.
+#[derive(Debug, PartialEq)]
+pub struct Annotation {
+    level: Level,
+    file: Option<String>,
+    line: Option<String>,
+    col: Option<String>,
+    title: Option<String>,
+    message: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Level {
+    Error,
+    Warning,
+    Notice,
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            Level::Error => write!(f, "error"),
+            Level::Warning => write!(f, "warning"),
+            Level::Notice => write!(f, "notice"),
+        }
+    }
+}
+
+impl Display for Annotation {
+    /// Formats the annotation as a compiler style diagnostic, such as
+    /// `src/lib.rs:10:5: error: message`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        let location = [&self.file, &self.line, &self.col]
+            .iter()
+            .map_while(|part| part.as_deref())
+            .collect::<Vec<_>>();
+        if !location.is_empty() {
+            write!(f, "{}: ", location.join(":"))?;
+        }
+        write!(f, "{}: ", self.level)?;
+        if let Some(title) = &self.title {
+            write!(f, "{}: ", title)?;
+        }
+
+        write!(f, "{}", self.message)
+    }
+}
+
+/// WorkflowCommands processes the output of steps, carrying out the
+/// workflow commands in it.
+#[derive(Default)]
+pub struct WorkflowCommands {
+    /// Whether the output within a group is hidden, rather than
+    /// indented under the group's title.
+    collapse_groups: bool,
+    /// Values which are replaced with `***` in the output, for the
+    /// rest of the run.
+    masks: Vec<String>,
+    /// The annotations created by every step.
+    annotations: Vec<Annotation>,
+    /// The token which resumes processing commands, after a step
+    /// stopped it with `::stop-commands::`.
+    stop_token: Option<String>,
+    /// The number of lines in the current group, if the step is in one.
+    group_lines: Option<usize>,
+}
+
+impl WorkflowCommands {
+    pub fn new(collapse_groups: bool) -> Self {
+        Self {
+            collapse_groups,
+            ..Self::default()
+        }
+    }
+
+    /// Returns the annotations created by every step so far.
+    pub fn annotations(&self) -> &[Annotation] {
+        &self.annotations
+    }
+
+    /// Returns a processor for a job which runs alongside others. It
+    /// starts with the masks of this one, and keeps its annotations
+    /// apart until it is joined back.
+    pub fn fork(&self) -> Self {
+        Self {
+            collapse_groups: self.collapse_groups,
+            masks: self.masks.clone(),
+            ..Self::default()
+        }
+    }
+
+    /// Adds the masks and annotations of a processor from `fork`.
+    pub fn join(&mut self, other: Self) {
+        for mask in other.masks {
+            if !self.masks.contains(&mask) {
+                self.masks.push(mask);
+            }
+        }
+        self.annotations.extend(other.annotations);
+    }
+
+    /// Prints the output of a step to `out`, as it is read, carrying
+    /// out any commands in it.
+    pub fn process(&mut self, output: impl Read, out: &mut impl Write) -> io::Result<()> {
+        let mut reader = BufReader::new(output);
+        let mut buffer = vec![];
+
+        while reader.read_until(b'\n', &mut buffer)? > 0 {
+            let line = String::from_utf8_lossy(&buffer);
+            let line = line.trim_end_matches(&['\r', '\n'][..]);
+            if let Some(output) = self.process_line(line) {
+                writeln!(out, "{}", output)?;
+            }
+            buffer.clear();
+        }
+        if let Some(output) = self.end_step() {
+            writeln!(out, "{}", output)?;
+        }
+
+        Ok(())
+    }
+
+    /// Processes one line of output, returning what should be printed
+    /// in its place.
+    fn process_line(&mut self, line: &str) -> Option<String> {
+        let (command, properties, value) = match parse(line) {
+            Some(parsed) => parsed,
+            None => return self.output(line),
+        };
+
+        if let Some(token) = &self.stop_token {
+            if command == token {
+                self.stop_token = None;
+                return None;
+            }
+            return self.output(line);
+        }
+
+        let value = unescape_data(value);
+        match command {
+            "add-mask" => {
+                if !value.is_empty() {
+                    self.masks.push(value);
+                }
+                None
+            }
+            "stop-commands" => {
+                self.stop_token = Some(value);
+                None
+            }
+            "group" => {
+                let title = format!("> {}", self.mask(&value));
+                let ended = self.end_group();
+                self.group_lines = Some(0);
+                match ended {
+                    Some(ended) => Some(format!("{}\n{}", ended, title)),
+                    None => Some(title),
+                }
+            }
+            "endgroup" => self.end_group(),
+            // Debug messages are only shown when debugging a workflow
+            "debug" => None,
+            "error" | "warning" | "notice" => {
+                let level = match command {
+                    "error" => Level::Error,
+                    "warning" => Level::Warning,
+                    _ => Level::Notice,
+                };
+                let property = |name: &str| {
+                    properties
+                        .split(',')
+                        .filter_map(|property| property.split_once('='))
+                        .find(|(key, _)| key.trim() == name)
+                        .map(|(_, value)| self.mask(&unescape_property(value.trim())))
+                };
+                let annotation = Annotation {
+                    level,
+                    file: property("file"),
+                    line: property("line"),
+                    col: property("col"),
+                    title: property("title"),
+                    message: self.mask(&value),
+                };
+                let shown = format!("{}", annotation);
+                self.annotations.push(annotation);
+
+                self.output_processed(shown)
+            }
+            _ => self.output(line),
+        }
+    }
+
+    /// Returns the line to print for ordinary output.
+    fn output(&mut self, line: &str) -> Option<String> {
+        let line = self.mask(line);
+
+        self.output_processed(line)
+    }
+
+    /// Returns the line to print for output which has already been
+    /// masked, taking the current group into account.
+    fn output_processed(&mut self, line: String) -> Option<String> {
+        match &mut self.group_lines {
+            Some(lines) if self.collapse_groups => {
+                *lines += 1;
+                None
+            }
+            Some(_) => Some(format!("  {}", line)),
+            None => Some(line),
+        }
+    }
+
+    fn mask(&self, text: &str) -> String {
+        self.masks.iter().fold(text.to_string(), |text, mask| {
+            text.replace(mask.as_str(), MASK)
+        })
+    }
+
+    /// Ends the current group, if there is one, returning the line
+    /// which reports how many of its lines were hidden.
+    fn end_group(&mut self) -> Option<String> {
+        let lines = self.group_lines.take()?;
+        if !self.collapse_groups || lines == 0 {
+            return None;
+        }
+
+        let plural = if lines == 1 { "" } else { "s" };
+        Some(format!("  ({} line{} hidden)", lines, plural))
+    }
+
+    /// Resets the state which doesn't outlive a step, returning the
+    /// line to print if it ends a group.
+    fn end_step(&mut self) -> Option<String> {
+        self.stop_token = None;
+        self.end_group()
+    }
+}
+
+/// Splits a workflow command into its name, properties and value.
+fn parse(line: &str) -> Option<(&str, &str, &str)> {
+    let rest = line.trim_start().strip_prefix("::")?;
+    let (command, value) = rest.split_once("::")?;
+    let (name, properties) = command.split_once(' ').unwrap_or((command, ""));
+    if name.is_empty() {
+        return None;
+    }
+
+    Some((name, properties, value))
+}
+
+fn unescape_data(value: &str) -> String {
+    value
+        .replace("%0D", "\r")
+        .replace("%0A", "\n")
+        .replace("%25", "%")
+}
+
+fn unescape_property(value: &str) -> String {
+    value
+        .replace("%0D", "\r")
+        .replace("%0A", "\n")
+        .replace("%3A", ":")
+        .replace("%2C", ",")
+        .replace("%25", "%")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn process_annotations() {
+        let mut commands = WorkflowCommands::new(false);
+
+        assert_eq!(
+            Some("src/lib.rs:10:5: error: Unused: unused variable `x`".to_string()),
+            commands.process_line(
+                "::error file=src/lib.rs,line=10,col=5,title=Unused::unused variable `x`"
+            )
+        );
+        assert_eq!(
+            Some("warning: 50%\ndone".to_string()),
+            commands.process_line("::warning::50%25%0Adone")
+        );
+        assert_eq!(2, commands.annotations().len());
+    }
+
+    #[test]
+    fn process_masks_and_groups() {
+        let mut commands = WorkflowCommands::new(false);
+
+        assert_eq!(None, commands.process_line("::add-mask::hunter2"));
+        assert_eq!(
+            Some("> Logging in".to_string()),
+            commands.process_line("::group::Logging in")
+        );
+        assert_eq!(
+            Some("  password ***".to_string()),
+            commands.process_line("password hunter2")
+        );
+        assert_eq!(None, commands.process_line("::endgroup::"));
+        assert_eq!(Some("done".to_string()), commands.process_line("done"));
+    }
+
+    #[test]
+    fn process_stop_commands() {
+        let mut commands = WorkflowCommands::new(false);
+
+        assert_eq!(None, commands.process_line("::stop-commands::pause"));
+        assert_eq!(
+            Some("::error::not an error".to_string()),
+            commands.process_line("::error::not an error")
+        );
+        assert_eq!(None, commands.process_line("::pause::"));
+        assert_eq!(
+            Some("error: an error".to_string()),
+            commands.process_line("::error::an error")
+        );
+        assert_eq!(1, commands.annotations().len());
+    }
+}
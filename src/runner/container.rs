@@ -0,0 +1,408 @@
+//! Running the steps of a job in its `container`, alongside its
+//! `services`, through a docker compatible command line tool such as
+//! `docker` or `podman`.
+
+use std::{
+    collections::BTreeMap,
+    env::temp_dir,
+    ffi::{OsStr, OsString},
+    fs,
+    path::{Path, PathBuf},
+    process::{self, Command, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::ci::{
+    github::{container::Container, shell},
+    Job, Shell,
+};
+
+/// How long services with a health check may take to become healthy.
+const SERVICE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Runs a step in the job's container. It records its process id, so
+/// the step can be killed if it times out, and prepends the directories
+/// added to the `PATH` to the container's own.
+const EXEC_WRAPPER: &str =
+    r#"echo $$ > "$0" && { [ -z "$1" ] || export PATH="$1:$PATH"; } && shift && exec "$@""#;
+
+/// ContainerRuntime builds the commands run against the container
+/// runtime, so any program accepting docker's arguments can be used.
+pub struct ContainerRuntime {
+    program: OsString,
+}
+
+impl ContainerRuntime {
+    pub fn new(program: impl Into<OsString>) -> Self {
+        Self {
+            program: program.into(),
+        }
+    }
+
+    fn command(&self) -> Command {
+        Command::new(&self.program)
+    }
+
+    /// Runs the runtime with the arguments, returning an error
+    /// describing the failure if it didn't succeed.
+    fn run<I, S>(&self, args: I) -> Result<(), String>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.output(args).map(|_| ())
+    }
+
+    /// Runs the runtime with the arguments, returning its output, or
+    /// an error describing the failure if it didn't succeed.
+    fn output<I, S>(&self, args: I) -> Result<String, String>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let args = args
+            .into_iter()
+            .map(|arg| arg.as_ref().to_os_string())
+            .collect::<Vec<_>>();
+        let output = self
+            .command()
+            .args(&args)
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| format!("failed to run {:?}: {}", self.program, e))?;
+        if output.status.success() {
+            return Ok(String::from_utf8_lossy(&output.stdout).trim().to_string());
+        }
+
+        let command = args
+            .iter()
+            .map(|arg| arg.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(" ");
+        Err(format!(
+            "`{} {}` failed: {}",
+            self.program.to_string_lossy(),
+            command,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+/// JobContainers are the containers started for a job, which are
+/// removed along with their network when dropped.
+pub struct JobContainers {
+    runtime: ContainerRuntime,
+    /// The network the containers share, once created.
+    network: Option<String>,
+    /// The names of the containers which have been started.
+    containers: Vec<String>,
+    /// The name of the container the steps run in, if the job has one.
+    job_container: Option<String>,
+    /// The file the process id of the running step is written to.
+    pid_file: PathBuf,
+    /// The shell steps without one run with in the job's container.
+    default_shell: Option<Shell>,
+}
+
+impl JobContainers {
+    /// Starts the service containers of the job, and then the
+    /// container its steps run in. The workspace and temporary
+    /// directory are mounted at the same paths, so the steps' scripts
+    /// and environment files can be found in the container.
+    pub fn start(runtime: ContainerRuntime, job: &Job, workspace: &Path) -> Result<Self, String> {
+        static NETWORK_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        let network = format!(
+            "belay-{}-{}",
+            process::id(),
+            NETWORK_COUNT.fetch_add(1, Ordering::SeqCst)
+        );
+        let mut containers = Self {
+            runtime,
+            network: None,
+            containers: vec![],
+            job_container: None,
+            pid_file: temp_dir().join(format!("{}.pid", network)),
+            default_shell: None,
+        };
+        containers.runtime.run(["network", "create", &network])?;
+        containers.network = Some(network.clone());
+
+        for (id, service) in job.services() {
+            let name = format!("{}-{}", network, id);
+            let mut args = run_args(&name, &network, service);
+            args.extend(vec![
+                "--network-alias".into(),
+                id.clone(),
+                service.image.clone(),
+            ]);
+            containers.runtime.run(&args)?;
+            containers.containers.push(name);
+        }
+        for (id, _) in job.services() {
+            containers.wait_until_healthy(id, &format!("{}-{}", network, id))?;
+        }
+
+        if let Some(container) = job.container() {
+            let name = format!("{}-job", network);
+            let workspace = workspace.to_string_lossy();
+            let temp = temp_dir();
+            let temp = temp.to_string_lossy();
+            let mut args = run_args(&name, &network, container);
+            args.extend(vec![
+                "-v".into(),
+                format!("{}:{}", workspace, workspace),
+                "-v".into(),
+                format!("{}:{}", temp, temp),
+                "-w".into(),
+                workspace.to_string(),
+                // Keep the container running, so each step can be run in it
+                "--entrypoint".into(),
+                "tail".into(),
+                container.image.clone(),
+                "-f".into(),
+                "/dev/null".into(),
+            ]);
+            containers.runtime.run(&args)?;
+            containers.containers.push(name.clone());
+
+            // As on GitHub, steps default to bash only if the image has it
+            let has_bash = containers
+                .runtime
+                .run(["exec", &name, "sh", "-c", "command -v bash"])
+                .is_ok();
+            containers.default_shell = Some(shell::unix_default(has_bash));
+            containers.job_container = Some(name);
+        }
+
+        Ok(containers)
+    }
+
+    /// Waits for a service with a health check to become healthy, as
+    /// its options may ask with `--health-cmd`.
+    fn wait_until_healthy(&self, id: &str, name: &str) -> Result<(), String> {
+        let deadline = Instant::now() + SERVICE_TIMEOUT;
+
+        loop {
+            let health = self.runtime.output([
+                "inspect",
+                "--format",
+                "{{if .State.Health}}{{.State.Health.Status}}{{end}}",
+                name,
+            ])?;
+            match health.as_str() {
+                // Services without a health check are ready once started
+                "" | "healthy" => return Ok(()),
+                "unhealthy" => return Err(format!("service '{}' is unhealthy", id)),
+                _ if Instant::now() >= deadline => {
+                    return Err(format!(
+                        "service '{}' wasn't healthy after {} seconds",
+                        id,
+                        SERVICE_TIMEOUT.as_secs()
+                    ))
+                }
+                _ => thread::sleep(Duration::from_millis(500)),
+            }
+        }
+    }
+
+    /// Returns the shell steps which don't set one run with in the job's
+    /// container, or `None` if the job has no container.
+    pub fn default_shell(&self) -> Option<&Shell> {
+        self.default_shell.as_ref()
+    }
+
+    /// Returns a command which runs the process in the job's container
+    /// instead, with the environment variables and working directory
+    /// it was given, and the directories added to the `PATH`, most
+    /// recent first. Returns `None` if the job has no container, so
+    /// its steps run on this machine.
+    pub fn exec(&self, process: &Command, paths: &[String]) -> Option<Command> {
+        let container = self.job_container.as_ref()?;
+
+        let mut exec = self.runtime.command();
+        exec.arg("exec");
+        if let Some(dir) = process.get_current_dir() {
+            exec.arg("-w").arg(dir);
+        }
+        // The PATH of this machine would hide the container's programs,
+        // so only the directories added to it are passed on
+        let vars = process
+            .get_envs()
+            .filter_map(|(k, v)| v.map(|v| (k, v)))
+            .filter(|(k, _)| *k != "PATH")
+            .collect::<BTreeMap<_, _>>();
+        for (k, v) in vars {
+            let mut var = k.to_os_string();
+            var.push("=");
+            var.push(v);
+            exec.arg("-e").arg(var);
+        }
+        exec.arg(container)
+            .arg("sh")
+            .arg("-c")
+            .arg(EXEC_WRAPPER)
+            .arg(&self.pid_file)
+            .arg(paths.join(":"))
+            .arg(process.get_program())
+            .args(process.get_args());
+
+        Some(exec)
+    }
+
+    /// Kills the step running in the job's container, along with any
+    /// processes it started. Killing the runtime's `exec` command
+    /// leaves the step running in the container.
+    pub fn kill_step(&self) {
+        let container = match &self.job_container {
+            Some(container) => container,
+            None => return,
+        };
+        let pid = match fs::read_to_string(&self.pid_file) {
+            Ok(pid) => pid.trim().to_string(),
+            Err(_) => return,
+        };
+
+        // The runtime runs each exec'd process in a new session, so its
+        // process group holds everything the step started
+        let _ = self.runtime.run([
+            "exec",
+            container,
+            "sh",
+            "-c",
+            &format!("kill -KILL -- -{0} 2>/dev/null || kill -KILL {0}", pid),
+        ]);
+    }
+}
+
+impl Drop for JobContainers {
+    fn drop(&mut self) {
+        if !self.containers.is_empty() {
+            let mut args = vec!["rm".to_string(), "-f".to_string()];
+            args.append(&mut self.containers);
+            let _ = self.runtime.run(&args);
+        }
+        if let Some(network) = self.network.take() {
+            let _ = self.runtime.run(["network", "rm", &network]);
+        }
+        let _ = fs::remove_file(&self.pid_file);
+    }
+}
+
+/// Returns the arguments to start a container in the background, up to
+/// but not including its image.
+fn run_args(name: &str, network: &str, container: &Container) -> Vec<String> {
+    let mut args = vec![
+        "run".to_string(),
+        "-d".to_string(),
+        "--name".to_string(),
+        name.to_string(),
+        "--network".to_string(),
+        network.to_string(),
+    ];
+
+    let env = container.env.iter().collect::<BTreeMap<_, _>>();
+    for (k, v) in env {
+        args.push("-e".into());
+        args.push(format!("{}={}", k, v));
+    }
+    for port in &container.ports {
+        args.push("-p".into());
+        args.push(port.clone());
+    }
+    for volume in &container.volumes {
+        args.push("-v".into());
+        args.push(volume.clone());
+    }
+    if let Some(options) = &container.options {
+        args.extend(shell_words(options));
+    }
+
+    args
+}
+
+/// Splits options into arguments as a shell would, so quoted values
+/// such as `--health-cmd "redis-cli ping"` stay one argument.
+fn shell_words(s: &str) -> Vec<String> {
+    let mut words = vec![];
+    let mut word = None::<String>;
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\\' => word.get_or_insert_with(String::new).extend(chars.next()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                word.extend(chars.by_ref().take_while(|&c| c != '\''));
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        // Within double quotes, a backslash only escapes
+                        // the characters which are special there
+                        '\\' => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => word.push('\\'),
+                        },
+                        c => word.push(c),
+                    }
+                }
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn run_args_keep_quoted_options() {
+        let service = Container {
+            image: "redis".to_string(),
+            env: HashMap::new(),
+            ports: vec![],
+            volumes: vec![],
+            options: Some(
+                r#"--health-cmd "redis-cli ping" --health-interval 10s --label 'a b'c"#.to_string(),
+            ),
+        };
+
+        let args = run_args("belay-1-redis", "belay-1", &service);
+
+        assert_eq!(
+            vec![
+                "--health-cmd",
+                "redis-cli ping",
+                "--health-interval",
+                "10s",
+                "--label",
+                "a bc"
+            ],
+            args[6..]
+        );
+    }
+
+    #[test]
+    fn split_shell_words() {
+        assert_eq!(
+            vec!["a", "", r#"b "c" \d"#, "e f"],
+            shell_words(r#"  a "" "b \"c\" \d"  e\ f "#)
+        );
+    }
+}
@@ -0,0 +1,392 @@
+//! Emulation of the environment GitHub Actions gives each step. This
+//! includes the default variables such as `GITHUB_SHA`, and the files
+//! through which a step can set environment variables, add to the
+//! `PATH`, set outputs and write a summary for the steps after it.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    env::temp_dir,
+    ffi::OsString,
+    fs, io,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::ci::{
+    github::{
+        action::Composite,
+        context,
+        expr::{self, ExpressionError},
+    },
+    Job, JobStatus, Task, Trigger,
+};
+
+/// JobEnvironment holds the state a job's steps pass to each other
+/// through the environment files.
+pub struct JobEnvironment {
+    /// The temporary directory holding the files.
+    dir: PathBuf,
+    /// The default variables, or none if they are disabled.
+    defaults: Vec<(String, String)>,
+    /// Variables written to `GITHUB_ENV`.
+    env: HashMap<String, String>,
+    /// Directories written to `GITHUB_PATH`, most recent first.
+    path: Vec<String>,
+    /// The `steps` context, of the steps with an `id`. Within a
+    /// composite action, only its own steps are included.
+    steps: BTreeMap<String, expr::Value>,
+    /// The composite actions whose steps are running, outermost first.
+    actions: Vec<RunningAction>,
+    /// Markdown written to `GITHUB_STEP_SUMMARY`.
+    summary: String,
+}
+
+/// RunningAction is a use of a composite action whose steps are
+/// running, see `JobEnvironment::enter_actions`.
+struct RunningAction {
+    composite: Composite,
+    /// The `steps` context of the step which used the action, which is
+    /// restored once the action's steps have run.
+    caller_steps: BTreeMap<String, expr::Value>,
+    /// Whether the step which used the action runs, which decides
+    /// whether any of the action's steps do.
+    runs: bool,
+    /// Whether one of the action's steps failed.
+    failed: bool,
+    /// The context the action's outputs are evaluated in, except for
+    /// the `steps` context.
+    context: Option<expr::Context>,
+}
+
+/// The environment variables which hold the paths to the files.
+const FILES: [&str; 4] = [
+    "GITHUB_ENV",
+    "GITHUB_PATH",
+    "GITHUB_OUTPUT",
+    "GITHUB_STEP_SUMMARY",
+];
+
+impl JobEnvironment {
+    /// Creates the environment for the job, which sets the default
+    /// variables unless `default_env` is false.
+    pub fn new(job: &Job, workspace: &Path, default_env: bool) -> io::Result<Self> {
+        static JOB_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        let mut dir = temp_dir();
+        dir.push(format!(
+            "belay-{}-job-{}",
+            process::id(),
+            JOB_COUNT.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::create_dir_all(&dir)?;
+
+        let mut defaults = vec![];
+        if default_env {
+            let github = context::github(job.trigger.as_ref(), workspace);
+            let event_path = dir.join("event.json");
+            fs::write(
+                &event_path,
+                github.get(&"event".into()).to_json().to_string(),
+            )?;
+
+            defaults = context::default_env(&github, &context::runner());
+            defaults.push((
+                "GITHUB_EVENT_PATH".into(),
+                event_path.to_string_lossy().into_owned(),
+            ));
+            defaults.push(("GITHUB_JOB".into(), job.id.clone()));
+        }
+
+        Ok(Self {
+            dir,
+            defaults,
+            env: HashMap::new(),
+            path: vec![],
+            steps: BTreeMap::new(),
+            actions: vec![],
+            summary: String::new(),
+        })
+    }
+
+    /// Passes the variables and outputs set by earlier steps on to the
+    /// task. Variables set in the workflow take precedence.
+    pub fn prepare(&self, task: &mut Task) {
+        for (k, v) in &self.env {
+            task.env.entry(k.clone()).or_insert_with(|| v.clone());
+        }
+        task.insert_context("steps", expr::Value::Object(self.steps.clone()));
+    }
+
+    /// Enters the composite actions the task was inlined from, leaving
+    /// those it wasn't. Entering an action evaluates the condition of the
+    /// step which used it, and the names of those which are skipped are
+    /// returned. Leaving an action records its outputs under the `id` of
+    /// the step which used it.
+    pub fn enter_actions(
+        &mut self,
+        task: &Task,
+        workspace: &Path,
+        trigger: Option<&Trigger>,
+        status: JobStatus,
+    ) -> Result<Vec<String>, ExpressionError> {
+        let composites = task.composites();
+        let entered = self
+            .actions
+            .iter()
+            .zip(composites)
+            .take_while(|(action, composite)| action.composite.key == composite.key)
+            .count();
+        while self.actions.len() > entered {
+            self.leave_action()?;
+        }
+
+        let mut skipped = vec![];
+        for composite in &composites[entered..] {
+            let runs = self.actions.last().is_none_or(|action| action.runs)
+                && task.composite_should_run(
+                    composite,
+                    expr::Value::Object(self.steps.clone()),
+                    workspace,
+                    trigger,
+                    status,
+                )?;
+            // Only the outermost action which is skipped is reported
+            if !runs && self.actions.last().is_none_or(|action| action.runs) {
+                skipped.push(composite.name.clone());
+            }
+
+            self.actions.push(RunningAction {
+                composite: composite.clone(),
+                caller_steps: std::mem::take(&mut self.steps),
+                runs,
+                failed: false,
+                context: task.composite_context(composite, workspace, trigger, status)?,
+            });
+        }
+
+        Ok(skipped)
+    }
+
+    /// Leaves all of the composite actions, once the job's steps have run.
+    pub fn leave_actions(&mut self) -> Result<(), ExpressionError> {
+        while !self.actions.is_empty() {
+            self.leave_action()?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether the steps of the current composite action are
+    /// skipped, since the step which used it was.
+    pub fn in_skipped_action(&self) -> bool {
+        self.actions.last().is_some_and(|action| !action.runs)
+    }
+
+    fn leave_action(&mut self) -> Result<(), ExpressionError> {
+        let action = match self.actions.pop() {
+            Some(action) => action,
+            None => return Ok(()),
+        };
+        let steps = std::mem::replace(&mut self.steps, action.caller_steps);
+        if action.failed {
+            if let Some(caller) = self.actions.last_mut() {
+                caller.failed = true;
+            }
+        }
+
+        let (outcome, outputs) = match action.context {
+            Some(mut context) if action.runs => {
+                context
+                    .values
+                    .insert("steps".into(), expr::Value::Object(steps));
+                let outputs = action
+                    .composite
+                    .outputs
+                    .iter()
+                    .map(|(name, value)| {
+                        Ok((
+                            name.clone(),
+                            expr::Value::String(expr::interpolate(value, &context)?),
+                        ))
+                    })
+                    .collect::<Result<_, ExpressionError>>()?;
+                let outcome = if action.failed {
+                    JobStatus::Failure
+                } else {
+                    JobStatus::Success
+                };
+
+                (outcome, outputs)
+            }
+            _ => (JobStatus::Skipped, BTreeMap::new()),
+        };
+        if let Some(id) = &action.composite.id {
+            self.steps.insert(
+                id.clone(),
+                expr::object(vec![
+                    ("outputs", expr::Value::Object(outputs)),
+                    ("outcome", outcome.to_string().as_str().into()),
+                    ("conclusion", outcome.to_string().as_str().into()),
+                ]),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Returns the variables to run a step with, including the default
+    /// variables and those pointing it at empty environment files.
+    pub fn command_env(&self, task: &Task) -> io::Result<Vec<(String, OsString)>> {
+        let mut vars = self
+            .defaults
+            .iter()
+            .map(|(k, v)| (k.clone(), OsString::from(v)))
+            .collect::<Vec<_>>();
+        if !self.defaults.is_empty() {
+            let action = task.id.clone().unwrap_or_else(|| "__run".to_string());
+            vars.push(("GITHUB_ACTION".into(), action.into()));
+        }
+        for name in &FILES {
+            let file = self.file(name);
+            fs::write(&file, "")?;
+            vars.push((name.to_string(), file.into_os_string()));
+        }
+
+        if !self.path.is_empty() {
+            let inherited = task
+                .env
+                .get("PATH")
+                .map(OsString::from)
+                .or_else(|| env::var_os("PATH"))
+                .unwrap_or_default();
+            let paths = self
+                .path
+                .iter()
+                .map(PathBuf::from)
+                .chain(env::split_paths(&inherited));
+            let path = env::join_paths(paths)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            vars.push(("PATH".into(), path));
+        }
+
+        Ok(vars)
+    }
+
+    /// Reads what the step wrote to the environment files, and records
+    /// its result in the `steps` context.
+    pub fn finish_step(&mut self, task: &Task, outcome: JobStatus) -> io::Result<()> {
+        let mut outputs = BTreeMap::new();
+
+        if outcome != JobStatus::Skipped {
+            self.env.extend(parse_variables(&self.read("GITHUB_ENV")?));
+            for line in self.read("GITHUB_PATH")?.lines().map(str::trim) {
+                if !line.is_empty() {
+                    self.path.insert(0, line.to_string());
+                }
+            }
+            outputs.extend(
+                parse_variables(&self.read("GITHUB_OUTPUT")?)
+                    .into_iter()
+                    .map(|(k, v)| (k, expr::Value::String(v))),
+            );
+            self.summary.push_str(&self.read("GITHUB_STEP_SUMMARY")?);
+        }
+
+        if outcome == JobStatus::Failure && !task.continues_on_error() {
+            if let Some(action) = self.actions.last_mut() {
+                action.failed = true;
+            }
+        }
+
+        if let Some(id) = &task.id {
+            // A failure which is tolerated still concludes as a success
+            let conclusion = match outcome {
+                JobStatus::Failure if task.continues_on_error() => JobStatus::Success,
+                outcome => outcome,
+            };
+            self.steps.insert(
+                id.clone(),
+                expr::object(vec![
+                    ("outputs", expr::Value::Object(outputs)),
+                    ("outcome", outcome.to_string().as_str().into()),
+                    ("conclusion", conclusion.to_string().as_str().into()),
+                ]),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Returns the directories the job's steps added to the `PATH`,
+    /// most recent first.
+    pub fn path(&self) -> &[String] {
+        &self.path
+    }
+
+    /// Returns the markdown the job's steps wrote to their summaries.
+    pub fn summary(&self) -> &str {
+        &self.summary
+    }
+
+    fn file(&self, name: &str) -> PathBuf {
+        self.dir.join(name.to_lowercase())
+    }
+
+    fn read(&self, name: &str) -> io::Result<String> {
+        match fs::read_to_string(self.file(name)) {
+            // The step may have deleted the file
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+            result => result,
+        }
+    }
+}
+
+impl Drop for JobEnvironment {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Parses the contents of `GITHUB_ENV` or `GITHUB_OUTPUT`, which hold
+/// lines of `NAME=value`, or multiline values written as
+/// `NAME<<DELIMITER` followed by the value and then the delimiter.
+fn parse_variables(contents: &str) -> Vec<(String, String)> {
+    let mut variables = vec![];
+    let mut lines = contents.lines();
+
+    while let Some(line) = lines.next() {
+        if let Some((name, value)) = line.split_once('=') {
+            variables.push((name.to_string(), value.to_string()));
+        } else if let Some((name, delimiter)) = line.split_once("<<") {
+            let value = lines
+                .by_ref()
+                .take_while(|line| *line != delimiter)
+                .collect::<Vec<_>>()
+                .join("\n");
+            variables.push((name.to_string(), value));
+        }
+    }
+
+    variables
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_environment_file() {
+        let contents = "VERSION=1.2.3\nNOTES<<EOF\nfirst line\nsecond=line\nEOF\n\nEMPTY=\n";
+
+        assert_eq!(
+            vec![
+                ("VERSION".to_string(), "1.2.3".to_string()),
+                ("NOTES".to_string(), "first line\nsecond=line".to_string()),
+                ("EMPTY".to_string(), String::new()),
+            ],
+            parse_variables(contents)
+        );
+    }
+}
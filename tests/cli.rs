@@ -5,7 +5,10 @@ use assert_fs::{
 };
 use predicates::prelude::{predicate, PredicateStrExt};
 
-use std::fs;
+use std::{
+    fs,
+    time::{Duration, Instant},
+};
 
 type TestResult = Result<(), Box<dyn std::error::Error>>;
 
@@ -154,7 +157,8 @@ fn belay_in_github_ci_dir() -> TestResult {
         .success()
         .stdout(
             predicate::str::similar(
-                r#"Checking 'Say hello':
+                r#"Skipping 'actions/checkout@v1', since it isn't needed locally
+Checking 'Say hello':
 hello
 Success!
 Checking 'Say goodbye':
@@ -169,7 +173,7 @@ Success!
 }
 
 #[test]
-fn belay_in_github_ci_dir_with_multiple_workflows() -> TestResult {
+fn belay_in_github_ci_dir_with_env() -> TestResult {
     let working_dir = TempDir::new()?;
 
     Command::new("git")
@@ -178,35 +182,122 @@ fn belay_in_github_ci_dir_with_multiple_workflows() -> TestResult {
         .assert()
         .success();
     fs::create_dir_all(working_dir.child(".github").child("workflows").path())?;
+    let github_yaml = include_str!("./github_env_integration_test.yml");
+    working_dir
+        .child(".github")
+        .child("workflows")
+        .child("rust.yml")
+        .write_str(github_yaml)?;
 
-    let github_yaml = include_str!("./github_passing_integration_test.yml");
+    Command::cargo_bin(crate_name!())?
+        .current_dir(working_dir.path())
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::similar(
+                r#"Checking 'Greet':
+hello job!
+Success!
+Checking 'Greet step':
+step says hello step!
+Success!
+"#,
+            )
+            .normalize(),
+        );
+
+    Ok(())
+}
+
+#[test]
+fn belay_in_github_ci_dir_with_working_directory() -> TestResult {
+    let working_dir = TempDir::new()?;
+
+    Command::new("git")
+        .arg("init")
+        .current_dir(working_dir.path())
+        .assert()
+        .success();
+    fs::create_dir_all(working_dir.child(".github").child("workflows").path())?;
+    let github_yaml = include_str!("./github_working_directory_integration_test.yml");
     working_dir
         .child(".github")
         .child("workflows")
         .child("rust.yml")
         .write_str(github_yaml)?;
-    let github_yaml = include_str!("./github_failing_integration_test.yml");
+    fs::create_dir_all(working_dir.child("backend").path())?;
+    fs::create_dir_all(working_dir.child("docs").path())?;
+    working_dir
+        .child("frontend")
+        .child("index.html")
+        .write_str("")?;
+
+    // Run from a sub-directory, to ensure working directories are
+    // resolved relative to the root of the repository.
+    Command::cargo_bin(crate_name!())?
+        .current_dir(working_dir.child("docs").path())
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::similar(
+                r#"Checking 'Workflow default':
+backend
+Success!
+Checking 'Step override':
+index.html
+Success!
+Checking 'Job default':
+docs
+Success!
+"#,
+            )
+            .normalize(),
+        );
+
+    Ok(())
+}
+
+#[test]
+fn belay_in_github_ci_dir_with_shell() -> TestResult {
+    let working_dir = TempDir::new()?;
+
+    Command::new("git")
+        .arg("init")
+        .current_dir(working_dir.path())
+        .assert()
+        .success();
+    fs::create_dir_all(working_dir.child(".github").child("workflows").path())?;
+    let github_yaml = include_str!("./github_shell_integration_test.yml");
     working_dir
         .child(".github")
         .child("workflows")
-        .child("rust2.yml")
+        .child("rust.yml")
         .write_str(github_yaml)?;
 
-    // workflows should run in alphabetical order, and scripts which
-    // are exactly the same should not be run again
+    // `shell: bash` sets pipefail, while the default shell does not, and a
+    // shell which isn't installed only fails its step
     Command::cargo_bin(crate_name!())?
         .current_dir(working_dir.path())
         .assert()
         .failure()
         .stdout(
             predicate::str::similar(
-                r#"Checking 'Say hello':
-hello
+                r#"Checking 'Default shell':
+default
 Success!
-Checking 'Say goodbye':
-goodbye
+Checking 'Custom shell':
+custom
+Success!
+Checking 'Bash shell':
+bash
+Checking 'Missing shell':
+Failed to run 'belay-missing-shell': No such file or directory (os error 2)
+Warning: 'Missing shell' failed, continuing since it has continue-on-error set
+Checking 'Missing shell again':
+Failed to run 'belay-missing-shell': No such file or directory (os error 2)
+Checking 'Report':
+reported
 Success!
-Checking 'tough test':
 "#,
             )
             .normalize(),
@@ -217,7 +308,7 @@ Checking 'tough test':
 }
 
 #[test]
-fn belay_in_gitlab_ci_dir() -> TestResult {
+fn belay_in_github_ci_dir_with_matrix() -> TestResult {
     let working_dir = TempDir::new()?;
 
     Command::new("git")
@@ -225,18 +316,38 @@ fn belay_in_gitlab_ci_dir() -> TestResult {
         .current_dir(working_dir.path())
         .assert()
         .success();
-    let github_yaml = include_str!("./gitlab_passing_integration_test.yml");
-    working_dir.child(".gitlab-ci.yml").write_str(github_yaml)?;
+    fs::create_dir_all(working_dir.child(".github").child("workflows").path())?;
+    let github_yaml = include_str!("./github_matrix_integration_test.yml");
+    working_dir
+        .child(".github")
+        .child("workflows")
+        .child("rust.yml")
+        .write_str(github_yaml)?;
 
     Command::cargo_bin(crate_name!())?
+        .arg("--matrix")
+        .arg("os=ubuntu-latest")
         .current_dir(working_dir.path())
         .assert()
         .success()
         .stdout(
             predicate::str::similar(
-                r#"Checking 'echo hello':
-hello
+                r#"Checking 'Test default (ubuntu-latest, default)':
+testing default on ubuntu-latest
 Success!
+Checking 'Test no-std (ubuntu-latest, no-std)':
+testing no-std on ubuntu-latest
+Success!
+Checking 'Test full (ubuntu-latest, full)':
+testing full on ubuntu-latest
+Success!
+Checking 'Lint':
+linting
+Success!
+Results of 'test':
+  test (ubuntu-latest, default): success
+  test (ubuntu-latest, no-std): success
+  test (ubuntu-latest, full): success
 "#,
             )
             .normalize(),
@@ -246,7 +357,7 @@ Success!
 }
 
 #[test]
-fn belay_in_github_ci_dir_fails() -> TestResult {
+fn belay_in_github_ci_dir_with_fail_fast() -> TestResult {
     let working_dir = TempDir::new()?;
 
     Command::new("git")
@@ -255,22 +366,311 @@ fn belay_in_github_ci_dir_fails() -> TestResult {
         .assert()
         .success();
     fs::create_dir_all(working_dir.child(".github").child("workflows").path())?;
-    let github_yaml = include_str!("./github_failing_integration_test.yml");
+    let github_yaml = include_str!("./github_fail_fast_integration_test.yml");
+    working_dir
+        .child(".github")
+        .child("workflows")
+        .child("rust.yml")
+        .write_str(github_yaml)?;
+
+    // the remaining combinations are cancelled once one fails, unless
+    // fail-fast is disabled. Combinations with a max-parallel run at
+    // once, and a failure cancels those still running
+    Command::cargo_bin(crate_name!())?
+        .current_dir(working_dir.path())
+        .assert()
+        .failure()
+        .stdout(
+            predicate::str::similar(
+                r#"Checking 'Test (stable)':
+testing on stable
+Success!
+Checking 'Test (beta)':
+testing on beta
+Cancelling 'test (nightly)', since 'test (beta)' failed
+Checking 'Lint (stable)':
+linting on stable, 1 at once, fail-fast false
+Success!
+Checking 'Lint (beta)':
+linting on beta, 1 at once, fail-fast false
+Checking 'Lint (nightly)':
+linting on nightly, 1 at once, fail-fast false
+Success!
+Checking 'Bench (slow)':
+Timed out
+Cancelling 'bench (slow)', since it exceeded its timeout
+Cancelling 'bench (fast)', since 'bench (slow)' failed
+Checking 'Pair (left)':
+both sides ready
+Success!
+Checking 'Pair (right)':
+both sides ready
+Success!
+Checking 'Race (fail)':
+Checking 'Race (wait)':
+Cancelling 'race (wait)', since 'race (fail)' failed
+Results of 'test':
+  test (stable): success
+  test (beta): failure
+  test (nightly): cancelled
+Results of 'lint':
+  lint (stable): success
+  lint (beta): failure
+  lint (nightly): success
+Results of 'bench':
+  bench (slow): cancelled
+  bench (fast): cancelled
+Results of 'pair':
+  pair (left): success
+  pair (right): success
+Results of 'race':
+  race (fail): failure
+  race (wait): cancelled
+"#,
+            )
+            .normalize(),
+        )
+        .stderr(predicate::str::similar("Error: \"Failed\"").trim());
+
+    Ok(())
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn belay_in_github_ci_dir_with_runs_on() -> TestResult {
+    let working_dir = TempDir::new()?;
+
+    Command::new("git")
+        .arg("init")
+        .current_dir(working_dir.path())
+        .assert()
+        .success();
+    fs::create_dir_all(working_dir.child(".github").child("workflows").path())?;
+    let github_yaml = include_str!("./github_matrix_integration_test.yml");
+    working_dir
+        .child(".github")
+        .child("workflows")
+        .child("rust.yml")
+        .write_str(github_yaml)?;
+
+    // combinations for other operating systems are skipped
+    Command::cargo_bin(crate_name!())?
+        .current_dir(working_dir.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Success!\nSkipping 'test (windows-latest, default)', since it runs on 'windows-latest'\nChecking 'Lint':",
+        ));
+
+    Command::cargo_bin(crate_name!())?
+        .arg("--ignore-runs-on")
+        .current_dir(working_dir.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("testing default on windows-latest"));
+
+    Ok(())
+}
+
+#[test]
+#[cfg(not(windows))]
+fn belay_in_github_ci_dir_with_container() -> TestResult {
+    use std::os::unix::fs::PermissionsExt;
+
+    let working_dir = TempDir::new()?;
+
+    Command::new("git")
+        .arg("init")
+        .current_dir(working_dir.path())
+        .assert()
+        .success();
+    fs::create_dir_all(working_dir.child(".github").child("workflows").path())?;
+    let github_yaml = include_str!("./github_container_integration_test.yml");
+    working_dir
+        .child(".github")
+        .child("workflows")
+        .child("rust.yml")
+        .write_str(github_yaml)?;
+
+    // a fake runtime which logs its arguments, runs the commands it is
+    // asked to exec on this machine, reports the service as healthy once
+    // it has been inspected before, and the job's image as lacking bash
+    let runtime = working_dir.child("runtime");
+    runtime.write_str(
+        r#"#!/bin/sh
+echo "$*" >> "$RUNTIME_LOG"
+if [ "$1" = inspect ]; then
+  if [ -e "$RUNTIME_LOG.inspected" ]; then
+    echo healthy
+  else
+    touch "$RUNTIME_LOG.inspected"
+    echo starting
+  fi
+fi
+if [ "$1" = exec ]; then
+  shift
+  while [ $# -gt 0 ]; do
+    case "$1" in
+      -w) cd "$2"; shift 2 ;;
+      -e) export "$2"; shift 2 ;;
+      *) break ;;
+    esac
+  done
+  shift
+  [ "$*" = "sh -c command -v bash" ] && exit 1
+  exec "$@"
+fi
+"#,
+    )?;
+    fs::set_permissions(runtime.path(), fs::Permissions::from_mode(0o755))?;
+    let log = working_dir.child("runtime.log");
+
+    Command::cargo_bin(crate_name!())?
+        .arg("--container-runtime")
+        .arg(runtime.path())
+        .env("RUNTIME_LOG", log.path())
+        .current_dir(working_dir.path())
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::similar(
+                r#"Checking 'Test':
+testing against postgres
+Success!
+Checking 'Install tools':
+Success!
+Checking 'Show path':
+path starts with /opt/tools/bin
+Success!
+Checking 'Hang':
+Timed out
+Warning: 'Hang' failed, continuing since it has continue-on-error set
+"#,
+            )
+            .normalize(),
+        );
+
+    let log = fs::read_to_string(log.path())?;
+    let lines = log.lines().collect::<Vec<_>>();
+    assert_eq!(13, lines.len(), "{}", log);
+    assert!(lines[0].starts_with("network create belay-"));
+    assert!(lines[1].starts_with("run -d --name belay-"));
+    assert!(lines[1].ends_with(
+        "-e POSTGRES_PASSWORD=postgres -p 5432:5432 --health-cmd pg_isready \
+         --network-alias postgres postgres:15"
+    ));
+    // the job container only starts once the service is healthy
+    assert!(lines[2].starts_with("inspect --format "));
+    assert!(lines[3].starts_with("inspect --format "));
+    assert!(lines[4].contains("-e CARGO_TERM_COLOR=always"));
+    assert!(lines[4].ends_with("--entrypoint tail rust:1.70 -f /dev/null"));
+    // steps use sh, since the image doesn't have bash
+    assert!(lines[5].ends_with("sh -c command -v bash"));
+    assert!(lines[6].starts_with("exec -w "));
+    assert!(lines[6].contains("-e DATABASE_HOST=postgres"));
+    assert!(lines[6].contains("-e GITHUB_ACTIONS=true"));
+    assert!(!lines[6].contains("-e PATH="));
+    assert!(lines[6].contains(" sh -e "));
+    assert!(!lines[6].contains("bash"));
+    // the timed out step is killed inside the container too
+    assert!(lines[9].starts_with("exec -w "));
+    assert!(lines[10].starts_with("exec belay-"));
+    assert!(lines[10].contains("kill -KILL"));
+    assert!(lines[11].starts_with("rm -f belay-"));
+    assert!(lines[12].starts_with("network rm belay-"));
+
+    Ok(())
+}
+
+#[test]
+fn belay_in_github_ci_dir_with_expressions() -> TestResult {
+    let working_dir = TempDir::new()?;
+
+    Command::new("git")
+        .arg("init")
+        .current_dir(working_dir.path())
+        .assert()
+        .success();
+    fs::create_dir_all(working_dir.child(".github").child("workflows").path())?;
+    let github_yaml = include_str!("./github_expressions_integration_test.yml");
+    working_dir
+        .child(".github")
+        .child("workflows")
+        .child("rust.yml")
+        .write_str(github_yaml)?;
+    working_dir
+        .child("crate")
+        .child("Cargo.lock")
+        .write_str("# lock")?;
+
+    let os_is_linux = cfg!(target_os = "linux");
+
+    Command::cargo_bin(crate_name!())?
+        .current_dir(working_dir.path())
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::similar(format!(
+                r#"Checking 'Build on stable (default)':
+cargo +stable build --features default
+Success!
+Checking 'Event (default)':
+push refs/heads/master {}-true
+Success!
+Checking 'Hash (default)':
+true .
+Success!
+Results of 'build':
+  build (default): success
+"#,
+                os_is_linux
+            ))
+            .normalize(),
+        );
+
+    Ok(())
+}
+
+#[test]
+fn belay_in_github_ci_dir_with_multiple_workflows() -> TestResult {
+    let working_dir = TempDir::new()?;
+
+    Command::new("git")
+        .arg("init")
+        .current_dir(working_dir.path())
+        .assert()
+        .success();
+    fs::create_dir_all(working_dir.child(".github").child("workflows").path())?;
+
+    let github_yaml = include_str!("./github_passing_integration_test.yml");
     working_dir
         .child(".github")
         .child("workflows")
         .child("rust.yml")
         .write_str(github_yaml)?;
+    let github_yaml = include_str!("./github_failing_integration_test.yml");
+    working_dir
+        .child(".github")
+        .child("workflows")
+        .child("rust2.yml")
+        .write_str(github_yaml)?;
 
+    // workflows should run in alphabetical order, and scripts which
+    // are exactly the same should not be run again
     Command::cargo_bin(crate_name!())?
         .current_dir(working_dir.path())
         .assert()
         .failure()
         .stdout(
             predicate::str::similar(
-                r#"Checking 'Say hello':
+                r#"Skipping 'actions/checkout@v1', since it isn't needed locally
+Checking 'Say hello':
 hello
 Success!
+Checking 'Say goodbye':
+goodbye
+Success!
+Skipping 'actions/checkout@v1', since it isn't needed locally
 Checking 'tough test':
 "#,
             )
@@ -282,7 +682,7 @@ Checking 'tough test':
 }
 
 #[test]
-fn belay_hook_push() -> TestResult {
+fn belay_in_gitlab_ci_dir() -> TestResult {
     let working_dir = TempDir::new()?;
 
     Command::new("git")
@@ -290,21 +690,1094 @@ fn belay_hook_push() -> TestResult {
         .current_dir(working_dir.path())
         .assert()
         .success();
+    let github_yaml = include_str!("./gitlab_passing_integration_test.yml");
+    working_dir.child(".gitlab-ci.yml").write_str(github_yaml)?;
 
     Command::cargo_bin(crate_name!())?
-        .arg("hook")
-        .arg("push")
         .current_dir(working_dir.path())
         .assert()
         .success()
-        .stdout(predicate::str::similar("Created hook `.git/hooks/pre-push`").trim());
+        .stdout(
+            predicate::str::similar(
+                r#"Stage 'main':
+Checking 'test':
+$ echo hello
+hello
+Success!
+"#,
+            )
+            .normalize(),
+        );
+
+    Ok(())
+}
 
-    assert!(working_dir
-        .child(".git")
-        .child("hooks")
-        .child("pre-push")
-        .path()
-        .exists());
+#[test]
+fn belay_in_gitlab_ci_dir_with_before_script() -> TestResult {
+    let working_dir = TempDir::new()?;
+
+    Command::new("git")
+        .arg("init")
+        .current_dir(working_dir.path())
+        .assert()
+        .success();
+    let gitlab_yaml = include_str!("./gitlab_before_script_integration_test.yml");
+    working_dir.child(".gitlab-ci.yml").write_str(gitlab_yaml)?;
+
+    // before_script shares the script's shell, while after_script runs
+    // in a new one even though the script failed
+    Command::cargo_bin(crate_name!())?
+        .current_dir(working_dir.path())
+        .assert()
+        .failure()
+        .stdout(
+            predicate::str::similar(
+                r#"Stage 'test':
+Checking 'test':
+$ export GREETING=hello
+$ mkdir -p build && cd build
+$ echo "$GREETING from $(basename "$PWD")"
+hello from build
+$ false
+"false" failed with exit code 1
+Checking 'test (after_script)':
+$ echo "after test, greeting '$GREETING'"
+after test, greeting ''
+Success!
+"#,
+            )
+            .normalize(),
+        )
+        .stderr(predicate::str::similar("Error: \"Failed\"").trim());
+
+    Ok(())
+}
+
+#[test]
+fn belay_in_gitlab_ci_dir_with_variables() -> TestResult {
+    let working_dir = TempDir::new()?;
+
+    Command::new("git")
+        .arg("init")
+        .current_dir(working_dir.path())
+        .assert()
+        .success();
+    let gitlab_yaml = include_str!("./gitlab_variables_integration_test.yml");
+    working_dir.child(".gitlab-ci.yml").write_str(gitlab_yaml)?;
+
+    // job variables override global ones, and may refer to them and to
+    // the predefined variables
+    Command::cargo_bin(crate_name!())?
+        .current_dir(working_dir.path())
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::similar(
+                r#"Stage 'test':
+Checking 'test':
+$ test "$CARGO_HOME" = "$PWD/.cargo" && echo "cargo home is in the project"
+cargo home is in the project
+$ test "$TARGET_DIR" = "$CARGO_HOME/target-release" && echo "target dir uses the job's profile"
+target dir uses the job's profile
+$ echo "backtrace $RUST_BACKTRACE, deploy $DEPLOY, job $CI_JOB_NAME in $CI_JOB_STAGE"
+backtrace 1, deploy no, job test in test
+Success!
+"#,
+            )
+            .normalize(),
+        );
+
+    Ok(())
+}
+
+#[test]
+fn belay_in_gitlab_ci_dir_with_stages() -> TestResult {
+    let working_dir = TempDir::new()?;
+
+    Command::new("git")
+        .arg("init")
+        .current_dir(working_dir.path())
+        .assert()
+        .success();
+    let gitlab_yaml = include_str!("./gitlab_stages_integration_test.yml");
+    working_dir.child(".gitlab-ci.yml").write_str(gitlab_yaml)?;
+
+    // jobs run stage by stage, and once a stage fails only the jobs
+    // whose needs succeeded still run
+    Command::cargo_bin(crate_name!())?
+        .current_dir(working_dir.path())
+        .assert()
+        .failure()
+        .stdout(
+            predicate::str::similar(
+                r#"Stage '.pre':
+Checking 'setup':
+$ echo setting up
+setting up
+Success!
+Stage 'build':
+Checking 'build':
+$ echo building
+building
+Success!
+Stage 'test':
+Checking 'lint':
+$ echo linting
+linting
+Success!
+Checking 'unit':
+$ echo testing
+testing
+$ false
+"false" failed with exit code 1
+Stage 'deploy':
+Skipping 'deploy'
+Checking 'docs':
+$ echo publishing docs
+publishing docs
+Success!
+"#,
+            )
+            .normalize(),
+        )
+        .stderr(predicate::str::similar("Error: \"Failed\"").trim());
+
+    working_dir
+        .child(".gitlab-ci.yml")
+        .write_str("stages: [build]\n\ntest:\n  script:\n    - cargo test\n")?;
+
+    Command::cargo_bin(crate_name!())?
+        .current_dir(working_dir.path())
+        .assert()
+        .failure()
+        .stderr(
+            predicate::str::similar(
+                r#"Error: "Job 'test' is in stage 'test', which isn't listed in `stages`""#,
+            )
+            .trim(),
+        );
+
+    Ok(())
+}
+
+#[test]
+fn belay_in_github_ci_dir_fails() -> TestResult {
+    let working_dir = TempDir::new()?;
+
+    Command::new("git")
+        .arg("init")
+        .current_dir(working_dir.path())
+        .assert()
+        .success();
+    fs::create_dir_all(working_dir.child(".github").child("workflows").path())?;
+    let github_yaml = include_str!("./github_failing_integration_test.yml");
+    working_dir
+        .child(".github")
+        .child("workflows")
+        .child("rust.yml")
+        .write_str(github_yaml)?;
+
+    Command::cargo_bin(crate_name!())?
+        .current_dir(working_dir.path())
+        .assert()
+        .failure()
+        .stdout(
+            predicate::str::similar(
+                r#"Skipping 'actions/checkout@v1', since it isn't needed locally
+Checking 'Say hello':
+hello
+Success!
+Checking 'tough test':
+"#,
+            )
+            .normalize(),
+        )
+        .stderr(predicate::str::similar("Error: \"Failed\"").trim());
+
+    Ok(())
+}
+
+#[test]
+fn belay_hook_push() -> TestResult {
+    let working_dir = TempDir::new()?;
+
+    Command::new("git")
+        .arg("init")
+        .current_dir(working_dir.path())
+        .assert()
+        .success();
+
+    Command::cargo_bin(crate_name!())?
+        .arg("hook")
+        .arg("push")
+        .current_dir(working_dir.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::similar("Created hook `.git/hooks/pre-push`").trim());
+
+    let hook = fs::read_to_string(
+        working_dir
+            .child(".git")
+            .child("hooks")
+            .child("pre-push")
+            .path(),
+    )?;
+    assert_eq!("#!/bin/sh\nbelay --from-hook pre-push", hook);
+
+    Ok(())
+}
+
+#[test]
+fn belay_in_github_ci_dir_with_conditions() -> TestResult {
+    let working_dir = TempDir::new()?;
+
+    Command::new("git")
+        .arg("init")
+        .current_dir(working_dir.path())
+        .assert()
+        .success();
+    fs::create_dir_all(working_dir.child(".github").child("workflows").path())?;
+    let github_yaml = include_str!("./github_conditions_integration_test.yml");
+    working_dir
+        .child(".github")
+        .child("workflows")
+        .child("rust.yml")
+        .write_str(github_yaml)?;
+
+    Command::cargo_bin(crate_name!())?
+        .current_dir(working_dir.path())
+        .assert()
+        .failure()
+        .stdout(
+            predicate::str::similar(
+                r#"Checking 'Build':
+build
+Success!
+Skipping 'Pull request only'
+Checking 'Test':
+Skipping 'Lint'
+Checking 'Report failure':
+failure
+Success!
+Checking 'Clean up':
+clean up
+Success!
+"#,
+            )
+            .normalize(),
+        );
+
+    Ok(())
+}
+
+#[test]
+fn belay_in_github_ci_dir_with_needs() -> TestResult {
+    let working_dir = TempDir::new()?;
+
+    Command::new("git")
+        .arg("init")
+        .current_dir(working_dir.path())
+        .assert()
+        .success();
+    fs::create_dir_all(working_dir.child(".github").child("workflows").path())?;
+    let github_yaml = include_str!("./github_needs_integration_test.yml");
+    working_dir
+        .child(".github")
+        .child("workflows")
+        .child("rust.yml")
+        .write_str(github_yaml)?;
+
+    Command::cargo_bin(crate_name!())?
+        .current_dir(working_dir.path())
+        .assert()
+        .failure()
+        .stdout(
+            predicate::str::similar(
+                r#"Checking 'Build':
+build
+Success!
+Checking 'Test':
+Skipping 'deploy-docs'
+Checking 'Report':
+test failure
+Success!
+"#,
+            )
+            .normalize(),
+        )
+        .stderr(predicate::str::similar("Error: \"Failed\"").trim());
+
+    Ok(())
+}
+
+#[test]
+fn belay_in_github_ci_dir_with_composite_action() -> TestResult {
+    let working_dir = TempDir::new()?;
+
+    Command::new("git")
+        .arg("init")
+        .current_dir(working_dir.path())
+        .assert()
+        .success();
+    fs::create_dir_all(working_dir.child(".github").child("workflows").path())?;
+    fs::create_dir_all(
+        working_dir
+            .child(".github")
+            .child("actions")
+            .child("greet")
+            .path(),
+    )?;
+    let github_yaml = include_str!("./github_composite_action_integration_test.yml");
+    working_dir
+        .child(".github")
+        .child("workflows")
+        .child("rust.yml")
+        .write_str(github_yaml)?;
+    let action_yaml = include_str!("./github_composite_action.yml");
+    working_dir
+        .child(".github")
+        .child("actions")
+        .child("greet")
+        .child("action.yml")
+        .write_str(action_yaml)?;
+
+    // the action's steps are inlined, with its inputs bound from `with`
+    // or else their defaults
+    Command::cargo_bin(crate_name!())?
+        .current_dir(working_dir.path())
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::similar(
+                r#"Skipping 'actions/checkout@v2', since it isn't needed locally
+Checking 'Say Howdy':
+Howdy world!
+Success!
+Checking 'Show action path':
+found action
+Success!
+Checking 'Say Hello':
+Hello world
+Success!
+Checking 'Show action path':
+found action
+Success!
+"#,
+            )
+            .normalize(),
+        );
+
+    Ok(())
+}
+
+#[test]
+fn belay_in_github_ci_dir_with_composite_action_outputs() -> TestResult {
+    let working_dir = TempDir::new()?;
+
+    Command::new("git")
+        .arg("init")
+        .current_dir(working_dir.path())
+        .assert()
+        .success();
+    fs::create_dir_all(working_dir.child(".github").child("workflows").path())?;
+    fs::create_dir_all(
+        working_dir
+            .child(".github")
+            .child("actions")
+            .child("version")
+            .path(),
+    )?;
+    let github_yaml = include_str!("./github_composite_action_outputs_integration_test.yml");
+    working_dir
+        .child(".github")
+        .child("workflows")
+        .child("release.yml")
+        .write_str(github_yaml)?;
+    let action_yaml = include_str!("./github_composite_action_outputs.yml");
+    working_dir
+        .child(".github")
+        .child("actions")
+        .child("version")
+        .child("action.yml")
+        .write_str(action_yaml)?;
+
+    // the action's outputs are recorded under the id of the step which
+    // used it, and once that step is skipped none of the action's steps
+    // run, even those which would run after a failure
+    Command::cargo_bin(crate_name!())?
+        .current_dir(working_dir.path())
+        .assert()
+        .failure()
+        .stdout(
+            predicate::str::similar(
+                r#"Checking 'Tag':
+Success!
+Checking 'Clean up':
+cleaning up after release-
+Success!
+Checking 'Show tag':
+tag release-1.2.3, outcome success, inner ''
+Success!
+Skipping 'Scheduled version'
+Checking 'Show skipped':
+scheduled skipped
+Success!
+Checking 'Fail':
+Skipping 'Late version'
+"#,
+            )
+            .normalize(),
+        );
+
+    Ok(())
+}
+
+#[test]
+fn belay_in_github_ci_dir_with_action_shims() -> TestResult {
+    let working_dir = TempDir::new()?;
+    let config_dir = TempDir::new()?;
+
+    Command::new("git")
+        .arg("init")
+        .current_dir(working_dir.path())
+        .assert()
+        .success();
+    fs::create_dir_all(working_dir.child(".github").child("workflows").path())?;
+    let github_yaml = include_str!("./github_action_shims_integration_test.yml");
+    working_dir
+        .child(".github")
+        .child("workflows")
+        .child("rust.yml")
+        .write_str(github_yaml)?;
+    fs::create_dir_all(config_dir.child("belay").path())?;
+    config_dir.child("belay").child("config.yml").write_str(
+        r#"command_blacklist: []
+action_shims:
+  octo-org/greet: echo "hello from ${{ inputs.who }}"
+  octo-org/setup: null
+"#,
+    )?;
+
+    let cargo_version = std::process::Command::new("cargo")
+        .arg("--version")
+        .env("RUSTUP_TOOLCHAIN", "stable")
+        .output()?
+        .stdout;
+
+    // well-known actions run as local commands, or are skipped if they
+    // aren't needed, and the config can add shims of its own
+    Command::cargo_bin(crate_name!())?
+        .current_dir(working_dir.path())
+        .env("XDG_CONFIG_HOME", config_dir.path())
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::similar(format!(
+                r#"Skipping 'actions/checkout@v4', since it isn't needed locally
+Skipping 'actions/cache@v4', since it isn't needed locally
+Checking 'dtolnay/rust-toolchain@stable':
+Success!
+Checking 'Toolchain':
+toolchain stable
+Success!
+Checking 'Cargo':
+{}Success!
+Checking 'octo-org/greet@v1':
+hello from push
+Success!
+Skipping 'octo-org/setup@v1', since it isn't needed locally
+"#,
+                String::from_utf8(cargo_version)?
+            ))
+            .normalize(),
+        );
+
+    Ok(())
+}
+
+#[test]
+fn belay_in_github_ci_dir_with_reusable_workflow() -> TestResult {
+    let working_dir = TempDir::new()?;
+    let config_dir = TempDir::new()?;
+
+    Command::new("git")
+        .arg("init")
+        .current_dir(working_dir.path())
+        .assert()
+        .success();
+    fs::create_dir_all(working_dir.child(".github").child("workflows").path())?;
+    let github_yaml = include_str!("./github_reusable_workflow_integration_test.yml");
+    working_dir
+        .child(".github")
+        .child("workflows")
+        .child("rust.yml")
+        .write_str(github_yaml)?;
+    let reusable_yaml = include_str!("./github_reusable_workflow.yml");
+    working_dir
+        .child(".github")
+        .child("workflows")
+        .child("common.yml")
+        .write_str(reusable_yaml)?;
+    fs::create_dir_all(config_dir.child("belay").path())?;
+    config_dir
+        .child("belay")
+        .child("config.yml")
+        .write_str("command_blacklist: []\nsecrets:\n  DEPLOY_TOKEN: hunter2\n")?;
+
+    // the called workflow's jobs replace the calling job, once for each
+    // combination of its matrix, and it only runs on its own when it's
+    // called. The callers' conditions see their own inputs, rather than
+    // those of the workflow they call
+    Command::cargo_bin(crate_name!())?
+        .current_dir(working_dir.path())
+        .env("XDG_CONFIG_HOME", config_dir.path())
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::similar(
+                r#"Checking 'Setup':
+setup
+Success!
+Checking 'Lint with nightly':
+lint nightly verbose=true
+Success!
+Checking 'Test':
+token hunter2
+Success!
+Checking 'Lint with beta':
+lint beta verbose=true
+Success!
+Checking 'Test':
+token hunter2
+Success!
+Skipping 'nightly/lint'
+Skipping 'nightly/test'
+Skipping 'octo-org/example-repo/.github/workflows/reusable.yml@main', since it calls a workflow which can't run locally
+Checking 'Report':
+lint success
+Success!
+"#,
+            )
+            .normalize(),
+        );
+
+    Ok(())
+}
+
+#[test]
+fn belay_in_github_ci_dir_with_environment_files() -> TestResult {
+    let working_dir = TempDir::new()?;
+
+    Command::new("git")
+        .arg("init")
+        .current_dir(working_dir.path())
+        .assert()
+        .success();
+    fs::create_dir_all(working_dir.child(".github").child("workflows").path())?;
+    let github_yaml = include_str!("./github_environment_files_integration_test.yml");
+    working_dir
+        .child(".github")
+        .child("workflows")
+        .child("rust.yml")
+        .write_str(github_yaml)?;
+
+    // variables, paths and outputs are passed to later steps of the same
+    // job, and summaries are shown once every job has run
+    Command::cargo_bin(crate_name!())?
+        .current_dir(working_dir.path())
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::similar(
+                r#"Checking 'Set version':
+Success!
+Checking 'Release v1.2.3':
+version 1.2.3
+tool ran
+Success!
+Checking 'Missing output':
+missing []
+Success!
+Checking 'Isolated':
+version unset
+Success!
+Summary of 'build':
+### Released 1.2.3
+"#,
+            )
+            .normalize(),
+        );
+
+    Ok(())
+}
+
+#[test]
+fn belay_in_github_ci_dir_with_workflow_commands() -> TestResult {
+    let working_dir = TempDir::new()?;
+
+    Command::new("git")
+        .arg("init")
+        .current_dir(working_dir.path())
+        .assert()
+        .success();
+    fs::create_dir_all(working_dir.child(".github").child("workflows").path())?;
+    let github_yaml = include_str!("./github_workflow_commands_integration_test.yml");
+    working_dir
+        .child(".github")
+        .child("workflows")
+        .child("rust.yml")
+        .write_str(github_yaml)?;
+
+    Command::cargo_bin(crate_name!())?
+        .current_dir(working_dir.path())
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::similar(
+                r#"Checking 'Lint':
+> Logging in
+  password ***
+src/lib.rs:10:5: error: unused variable
+src/main.rs: warning: *** in stderr
+::warning::not a warning
+notice: Done: lint finished
+Success!
+Annotations:
+src/lib.rs:10:5: error: unused variable
+src/main.rs: warning: *** in stderr
+notice: Done: lint finished
+"#,
+            )
+            .normalize(),
+        );
+
+    // the output of groups can be hidden
+    Command::cargo_bin(crate_name!())?
+        .arg("--collapse-groups")
+        .current_dir(working_dir.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(
+            "Checking 'Lint':\n> Logging in\n  (1 line hidden)\nsrc/lib.rs",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn belay_in_github_ci_dir_with_default_env() -> TestResult {
+    let working_dir = TempDir::new()?;
+    let config_dir = TempDir::new()?;
+
+    Command::new("git")
+        .arg("init")
+        .current_dir(working_dir.path())
+        .assert()
+        .success();
+    fs::create_dir_all(working_dir.child(".github").child("workflows").path())?;
+    let github_yaml = include_str!("./github_default_env_integration_test.yml");
+    working_dir
+        .child(".github")
+        .child("workflows")
+        .child("rust.yml")
+        .write_str(github_yaml)?;
+
+    Command::cargo_bin(crate_name!())?
+        .current_dir(working_dir.path())
+        .env("XDG_CONFIG_HOME", config_dir.path())
+        .env_remove("CI")
+        .env_remove("GITHUB_ACTIONS")
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::similar(
+                r#"Checking 'Show env':
+ci=true actions=true job=build action=show
+os=set workspace=root
+Success!
+"#,
+            )
+            .normalize(),
+        );
+
+    // the default variables can be disabled in the config file
+    fs::create_dir_all(config_dir.child("belay").path())?;
+    config_dir
+        .child("belay")
+        .child("config.yml")
+        .write_str("command_blacklist: []\ndefault_env: false\n")?;
+
+    Command::cargo_bin(crate_name!())?
+        .current_dir(working_dir.path())
+        .env("XDG_CONFIG_HOME", config_dir.path())
+        .env_remove("CI")
+        .env_remove("GITHUB_ACTIONS")
+        .env_remove("GITHUB_JOB")
+        .env_remove("GITHUB_ACTION")
+        .env_remove("GITHUB_WORKSPACE")
+        .env_remove("RUNNER_OS")
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::similar(
+                r#"Checking 'Show env':
+ci=unset actions=unset job=unset action=unset
+os= workspace=
+Success!
+"#,
+            )
+            .normalize(),
+        );
+
+    Ok(())
+}
+
+#[test]
+fn belay_in_github_ci_dir_with_continue_on_error() -> TestResult {
+    let working_dir = TempDir::new()?;
+
+    Command::new("git")
+        .arg("init")
+        .current_dir(working_dir.path())
+        .assert()
+        .success();
+    fs::create_dir_all(working_dir.child(".github").child("workflows").path())?;
+    let github_yaml = include_str!("./github_continue_on_error_integration_test.yml");
+    working_dir
+        .child(".github")
+        .child("workflows")
+        .child("rust.yml")
+        .write_str(github_yaml)?;
+
+    let start = Instant::now();
+    Command::cargo_bin(crate_name!())?
+        .current_dir(working_dir.path())
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::similar(
+                r#"Checking 'Miri':
+Warning: 'Miri' failed, continuing since it has continue-on-error set
+Checking 'Build':
+build
+Success!
+Checking 'Sleep':
+Timed out
+Skipping 'Unreachable'
+Warning: 'slow' failed, continuing since it has continue-on-error set
+"#,
+            )
+            .normalize(),
+        );
+    // The sleep should be killed long before it would finish
+    assert!(start.elapsed() < Duration::from_secs(20));
+
+    Ok(())
+}
+
+#[test]
+fn belay_in_github_ci_dir_with_paths() -> TestResult {
+    let working_dir = TempDir::new()?;
+
+    Command::new("git")
+        .arg("init")
+        .current_dir(working_dir.path())
+        .assert()
+        .success();
+    fs::create_dir_all(working_dir.child(".github").child("workflows").path())?;
+    working_dir
+        .child(".github")
+        .child("workflows")
+        .child("docs.yml")
+        .write_str(include_str!("./github_paths_docs_integration_test.yml"))?;
+    working_dir
+        .child(".github")
+        .child("workflows")
+        .child("rust.yml")
+        .write_str(include_str!("./github_paths_rust_integration_test.yml"))?;
+
+    // Only the staged markdown file is considered from the pre-commit hook
+    working_dir
+        .child("docs")
+        .child("intro.md")
+        .write_str("# Intro")?;
+    working_dir.child("main.rs").write_str("fn main() {}")?;
+    Command::new("git")
+        .arg("add")
+        .arg("docs")
+        .current_dir(working_dir.path())
+        .assert()
+        .success();
+
+    Command::cargo_bin(crate_name!())?
+        .arg("--from-hook")
+        .arg("pre-commit")
+        .current_dir(working_dir.path())
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::similar(
+                r#"Checking 'Build docs':
+building docs
+Success!
+"#,
+            )
+            .normalize(),
+        );
+
+    Command::new("git")
+        .arg("add")
+        .arg("main.rs")
+        .current_dir(working_dir.path())
+        .assert()
+        .success();
+
+    Command::cargo_bin(crate_name!())?
+        .arg("--from-hook")
+        .arg("pre-commit")
+        .current_dir(working_dir.path())
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::similar(
+                r#"Checking 'Build docs':
+building docs
+Success!
+Checking 'Build':
+building
+Success!
+"#,
+            )
+            .normalize(),
+        );
+
+    Ok(())
+}
+
+#[test]
+fn belay_in_github_ci_dir_with_pull_request() -> TestResult {
+    let temp_dir = TempDir::new()?;
+    let remote_dir = temp_dir.child("remote.git");
+    let working_dir = temp_dir.child("work");
+    fs::create_dir_all(remote_dir.path())?;
+    fs::create_dir_all(working_dir.path())?;
+
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .args([
+                "-c",
+                "user.name='Josh'",
+                "-c",
+                "user.email='Josh@email.com'",
+            ])
+            .args(args)
+            .current_dir(working_dir.path())
+            .assert()
+            .success();
+    };
+    Command::new("git")
+        .arg("init")
+        .arg("--bare")
+        .current_dir(remote_dir.path())
+        .assert()
+        .success();
+    git(&["init"]);
+
+    fs::create_dir_all(working_dir.child(".github").child("workflows").path())?;
+    working_dir
+        .child(".github")
+        .child("workflows")
+        .child("pull_request.yml")
+        .write_str(include_str!("./github_pull_request_integration_test.yml"))?;
+    working_dir
+        .child(".github")
+        .child("workflows")
+        .child("release.yml")
+        .write_str(include_str!(
+            "./github_pull_request_release_integration_test.yml"
+        ))?;
+    git(&["add", "."]);
+    git(&["commit", "-m", "\"test commit\""]);
+    git(&["branch", "-M", "main"]);
+
+    // Pull requests are expected since there is an upstream remote, and
+    // they are expected to target the default branch of origin
+    let remote_path = remote_dir.path().to_string_lossy().to_string();
+    git(&["remote", "add", "origin", &remote_path]);
+    git(&["remote", "add", "upstream", &remote_path]);
+    git(&["push", "origin", "main"]);
+    git(&["remote", "set-head", "origin", "main"]);
+    git(&["checkout", "-b", "feature"]);
+
+    Command::cargo_bin(crate_name!())?
+        .current_dir(working_dir.path())
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::similar(
+                r#"Checking 'Target':
+targeting main on opened
+Success!
+"#,
+            )
+            .normalize(),
+        );
+
+    // Once the branch is pushed, pushing again updates the pull request
+    git(&["push", "--set-upstream", "origin", "feature"]);
+
+    Command::cargo_bin(crate_name!())?
+        .current_dir(working_dir.path())
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::similar(
+                r#"Checking 'Target':
+targeting main on synchronize
+Success!
+"#,
+            )
+            .normalize(),
+        );
+
+    Ok(())
+}
+
+#[test]
+fn belay_in_github_ci_dir_with_event() -> TestResult {
+    let working_dir = TempDir::new()?;
+
+    Command::new("git")
+        .arg("init")
+        .current_dir(working_dir.path())
+        .assert()
+        .success();
+    fs::create_dir_all(working_dir.child(".github").child("workflows").path())?;
+    working_dir
+        .child(".github")
+        .child("workflows")
+        .child("nightly.yml")
+        .write_str(include_str!("./github_schedule_integration_test.yml"))?;
+    working_dir
+        .child(".github")
+        .child("workflows")
+        .child("release.yml")
+        .write_str(include_str!(
+            "./github_workflow_dispatch_integration_test.yml"
+        ))?;
+
+    // Neither workflow runs on push
+    Command::cargo_bin(crate_name!())?
+        .current_dir(working_dir.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::similar(""));
+
+    Command::cargo_bin(crate_name!())?
+        .arg("--event")
+        .arg("schedule")
+        .current_dir(working_dir.path())
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::similar(
+                r#"Checking 'Fuzz':
+fuzzing
+Success!
+"#,
+            )
+            .normalize(),
+        );
+
+    Command::cargo_bin(crate_name!())?
+        .arg("--event")
+        .arg("workflow_dispatch")
+        .arg("--input")
+        .arg("version=1.2.3")
+        .current_dir(working_dir.path())
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::similar(
+                r#"Checking 'Release':
+releasing 1.2.3 from workflow_dispatch
+Success!
+"#,
+            )
+            .normalize(),
+        );
+
+    Command::cargo_bin(crate_name!())?
+        .arg("--event")
+        .arg("workflow_dispatch")
+        .arg("--input")
+        .arg("version=1.2.3")
+        .arg("--input")
+        .arg("dry-run=false")
+        .current_dir(working_dir.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::similar("Skipping 'release'\n").normalize());
+
+    Command::cargo_bin(crate_name!())?
+        .arg("--event")
+        .arg("workflow_dispatch")
+        .current_dir(working_dir.path())
+        .assert()
+        .failure()
+        .stderr(predicate::str::similar(r#"Error: "Missing required input 'version'""#).trim());
+
+    Ok(())
+}
+
+#[test]
+fn belay_with_inputs_for_one_of_several_dispatch_workflows() -> TestResult {
+    let working_dir = TempDir::new()?;
+
+    Command::new("git")
+        .arg("init")
+        .current_dir(working_dir.path())
+        .assert()
+        .success();
+    fs::create_dir_all(working_dir.child(".github").child("workflows").path())?;
+    working_dir
+        .child(".github")
+        .child("workflows")
+        .child("docs.yml")
+        .write_str(include_str!(
+            "./github_workflow_dispatch_docs_integration_test.yml"
+        ))?;
+    working_dir
+        .child(".github")
+        .child("workflows")
+        .child("release.yml")
+        .write_str(include_str!(
+            "./github_workflow_dispatch_integration_test.yml"
+        ))?;
+
+    // Each workflow ignores the inputs only the other one declares
+    Command::cargo_bin(crate_name!())?
+        .arg("--event")
+        .arg("workflow_dispatch")
+        .arg("--input")
+        .arg("version=1.2.3")
+        .arg("--input")
+        .arg("target=api")
+        .current_dir(working_dir.path())
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::similar(
+                r#"Checking 'Docs':
+building docs for api
+Success!
+Checking 'Release':
+releasing 1.2.3 from workflow_dispatch
+Success!
+"#,
+            )
+            .normalize(),
+        );
 
     Ok(())
 }